@@ -0,0 +1,23 @@
+// a post-processing pass for --config: dims the page away from the cursor,
+// for reading a line at a time. see the README for the bindings
+
+struct Post {
+    resolution: vec2<f32>,
+    page_size: vec2<f32>,
+    cursor: vec2<f32>,
+    time: f32,
+}
+
+@group(0) @binding(0) var source: texture_2d<f32>;
+@group(0) @binding(1) var source_sampler: sampler;
+@group(0) @binding(2) var<uniform> post: Post;
+
+@fragment
+fn fs_main(@location(0) uv: vec2<f32>) -> @location(0) vec4<f32> {
+    let color = textureSample(source, source_sampler, uv);
+    let pixel = uv * post.resolution;
+    // a band a few lines tall around the cursor, fading out over 40 pixels
+    let away = max(abs(pixel.y - post.cursor.y) - 30.0, 0.0);
+    let light = mix(1.0, 0.25, smoothstep(0.0, 40.0, away));
+    return vec4<f32>(color.rgb * light, 1.0);
+}
@@ -0,0 +1,118 @@
+// page turn latency: allocating a fresh texture, bind group layout and bind
+// group for every page, against one layout and textures reused from the
+// pool. run with `cargo bench --bench page_turn`
+use std::time::{Duration, Instant};
+
+use pdf_rust::test_support::{Converter, Format, Pixels, Pool, Texture};
+
+// a letter page at the default raster scale of 3
+const WIDTH: u32 = 1836;
+const HEIGHT: u32 = 2376;
+const TURNS: u32 = 50;
+
+fn main() {
+    let Some((device, queue)) = pollster::block_on(open_device()) else {
+        eprintln!("page_turn: no gpu adapter, skipping");
+        return;
+    };
+    let bytes = vec![255u8; (WIDTH * HEIGHT * 4) as usize];
+    let pixels = Pixels {
+        bytes: &bytes,
+        width: WIDTH,
+        height: HEIGHT,
+        stride: 4 * WIDTH,
+        format: Format::RGBA,
+    };
+    let converter = Converter::new(&device);
+
+    let fresh = time(&device, &queue, || {
+        let layout = bind_group_layout(&device);
+        let texture =
+            Texture::from_bytes(&device, &queue, &converter, &pixels, "page", None)
+                .unwrap();
+        bind_group(&device, &layout, &texture);
+    });
+
+    let layout = bind_group_layout(&device);
+    let mut pool = Pool::new(4, None);
+    let mut shown: Option<Texture> = None;
+    let pooled = time(&device, &queue, || {
+        let texture = pool
+            .upload(&device, &queue, &converter, &pixels, "page")
+            .unwrap();
+        bind_group(&device, &layout, &texture);
+        if let Some(old) = shown.replace(texture) {
+            pool.recycle(old);
+        }
+    });
+
+    println!("fresh textures: {:>8.2?} per page turn", fresh);
+    println!("pooled:         {:>8.2?} per page turn", pooled);
+}
+
+// average time of one turn, waiting for the gpu to finish each
+fn time(device: &wgpu::Device, queue: &wgpu::Queue, mut turn: impl FnMut()) -> Duration {
+    let start = Instant::now();
+    for _ in 0..TURNS {
+        turn();
+        queue.submit(std::iter::empty());
+        device.poll(wgpu::Maintain::Wait);
+    }
+    start.elapsed() / TURNS
+}
+
+async fn open_device() -> Option<(wgpu::Device, wgpu::Queue)> {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+    let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptions::default())
+        .await?;
+    adapter
+        .request_device(&wgpu::DeviceDescriptor::default(), None)
+        .await
+        .ok()
+}
+
+fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ],
+        label: Some("texture_bind_group_layout"),
+    })
+}
+
+fn bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    texture: &Texture,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&texture.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&texture.sampler),
+            },
+        ],
+        label: Some("diffuse_bind_group"),
+    })
+}
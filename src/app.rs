@@ -1,108 +1,153 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
 use egui_winit::winit;
 use egui_winit::winit::{
     event::*,
-    event_loop::EventLoop,
+    event_loop::{EventLoop, EventLoopProxy},
     window::{Window, WindowBuilder},
 };
-use wgpu::util::DeviceExt;
+use anyhow::{anyhow, Context, Result};
 
 use mupdf::document::Document;
-use mupdf::{self, Matrix, Point};
+use mupdf::pdf::PdfDocument;
+
+#[cfg(feature = "cpu")]
+use pdf_rust::fallback::Rasterizer;
+use pdf_rust::{Command, Event as ViewerEvent, KeyParser, Link, Offscreen, Settings, View, Viewer};
+
+use crate::backend::{self, Backend};
+use crate::cli::Args;
+use crate::config::Config;
+#[cfg(feature = "cpu")]
+use crate::cpu;
+use crate::headings;
+use crate::labels::PageLabels;
+use crate::outline::{Entry, Toc};
+use crate::ui::{Action, Ui, UiView};
+use crate::watch::Watcher;
+
+// the page shader in the source tree, reloaded from there with --watch-shaders
+const SHADER: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/shader.wgsl");
 
-use crate::texture;
+struct State {
+    //graphics data
+    surface: wgpu::Surface,
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    config: wgpu::SurfaceConfiguration,
+    size: winit::dpi::PhysicalSize<u32>,
+    window: Window,
+    // the document on screen, and the overlay on top of it
+    viewer: Viewer,
+    ui: Ui,
+    //interal data
+    filename: String,
+    toc: bool,
+    outline: Toc,
+    // the outline being generated for a document without one
+    headings: Option<Receiver<Result<Vec<Entry>>>>,
+    labels: PageLabels,
+    keys: KeyParser,
+    notice: Option<String>,
+    // the config file's settings, read again as its shaders change
+    user_config: Config,
+    // with --watch-shaders, what tells us the shaders changed on disk
+    watcher: Option<Watcher>,
+    // what failed to compile, page shader and post passes apart so
+    // reloading one leaves the other's, until the user closes them
+    shader_errors: Vec<String>,
+    post_errors: Vec<String>,
+    // what the device reported outside of any error scope
+    gpu_errors: Vec<String>,
+    backend: Backend,
+    sink: ErrorSink,
+    // the surface was lost and configured again; losing it once more
+    // before a frame gets through means the device went with it
+    surface_lost: bool,
+}
 
-#[repr(C)]
-#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
-struct Vertex {
-    position: [f32; 3],
-    tex_coords: [f32; 2],
-    bg_color: [f32; 3],
+// what the device reports outside of error scopes. the handler runs on
+// whatever thread wgpu notices on, so it leaves things here and wakes the
+// event loop to pick them up
+#[derive(Default)]
+struct Uncaptured {
+    // set when the device is lost, to rebuild everything on the next frame
+    lost: AtomicBool,
+    errors: Mutex<Vec<String>>,
 }
 
-const SQUARE: &[Vertex] = &[
-    Vertex {
-        position: [-1.0, 1.0, 0.0],
-        tex_coords: [0.0, 0.0],
-        bg_color: [255.0, 255.0, 255.0],
-    },
-    Vertex {
-        position: [-1.0, -1.0, 0.0],
-        tex_coords: [0.0, 1.0],
-        bg_color: [255.0, 255.0, 255.0],
-    },
-    Vertex {
-        position: [1.0, -1.0, 0.0],
-        tex_coords: [1.0, 1.0],
-        bg_color: [255.0, 255.0, 255.0],
-    },
-    Vertex {
-        position: [1.0, 1.0, 0.0],
-        tex_coords: [1.0, 0.0],
-        bg_color: [255.0, 255.0, 255.0],
-    },
-];
-
-const SQUARE_INDICES: &[u16] = &[0, 1, 3, 1, 2, 3];
-
-impl Vertex {
-    fn desc() -> wgpu::VertexBufferLayout<'static> {
-        wgpu::VertexBufferLayout {
-            array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &[
-                wgpu::VertexAttribute {
-                    offset: 0,
-                    shader_location: 0,
-                    format: wgpu::VertexFormat::Float32x3,
-                },
-                wgpu::VertexAttribute {
-                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
-                    shader_location: 1,
-                    format: wgpu::VertexFormat::Float32x2,
-                },
-                wgpu::VertexAttribute {
-                    offset: std::mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
-                    shader_location: 2,
-                    format: wgpu::VertexFormat::Float32x3,
-                },
-            ],
+// where a device's uncaptured errors go, and how the event loop hears
+// about them
+#[derive(Clone)]
+struct ErrorSink {
+    uncaptured: Arc<Uncaptured>,
+    proxy: EventLoopProxy<()>,
+}
+
+// the document as opened on the command line, for the page labels, the
+// window size and the cpu fallback. pdfs are kept as such so their labels
+// can be read. the viewer, the rasterizer and the outline generator each
+// open the file again by name on their own threads, so documents that
+// need a password are not supported
+pub enum Doc {
+    Pdf(PdfDocument),
+    Other(Document),
+}
+
+impl Doc {
+    fn open(filename: &str) -> Result<Self> {
+        let doc =
+            Document::open(filename).with_context(|| format!("could not open {}", filename))?;
+        if !doc.is_pdf() {
+            return Ok(Doc::Other(doc));
+        }
+        Ok(Doc::Pdf(PdfDocument::try_from(doc)?))
+    }
+
+    fn labels(&self) -> PageLabels {
+        match self {
+            Doc::Pdf(pdf) => PageLabels::load(pdf),
+            Doc::Other(_) => PageLabels::default(),
         }
     }
 }
 
-struct State {
-    //graphics data
+impl std::ops::Deref for Doc {
+    type Target = Document;
+
+    fn deref(&self) -> &Document {
+        match self {
+            Doc::Pdf(pdf) => pdf,
+            Doc::Other(doc) => doc,
+        }
+    }
+}
+
+// everything made from the device, rebuilt together when it is lost
+struct Gpu {
     surface: wgpu::Surface,
-    device: wgpu::Device,
-    queue: wgpu::Queue,
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
     config: wgpu::SurfaceConfiguration,
-    size: winit::dpi::PhysicalSize<u32>,
-    window: Window,
-    render_pipeline: wgpu::RenderPipeline,
-    vertex_buffer: wgpu::Buffer,
-    index_buffer: wgpu::Buffer,
-    num_indices: u32,
-    diffuse_bind_group: wgpu::BindGroup,
-    diffuse_texture: texture::Texture,
-    //interal data
-    doc: mupdf::Document,
-    toc: bool,
-    page: i32,
-    pos: Point,
-    res: f32,
-    color: wgpu::Color,
 }
 
-impl State {
+impl Gpu {
     // Creating some of the wgpu types requires async code
-    // Generate a new State from a window and doc
-    async fn new(window: Window, doc: mupdf::Document) -> Self {
-        let size = window.inner_size();
-
+    async fn new(
+        window: &Window,
+        size: winit::dpi::PhysicalSize<u32>,
+        backend: Backend,
+        sink: ErrorSink,
+    ) -> Result<Self> {
         // The instance is a handle to our GPU
-        // Backends::all => Vulkan + Metal + DX12 + Browser WebGPU
+        // Backends::all => Vulkan + Metal + DX12 + Browser WebGPU, unless
+        // one was picked on the command line
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::all(),
+            backends: backend.backends(),
             dx12_shader_compiler: Default::default(),
         });
 
@@ -110,16 +155,9 @@ impl State {
         //
         // The surface needs to live as long as the window that created it.
         // State owns the window so this should be safe.
-        let surface = unsafe { instance.create_surface(&window) }.unwrap();
-
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-            .await
-            .unwrap();
+        let surface = unsafe { instance.create_surface(window) }?;
+
+        let adapter = backend::request_adapter(&instance, &surface, backend).await?;
 
         let (device, queue) = adapter
             .request_device(
@@ -129,15 +167,18 @@ impl State {
                     // we're building for the web we'll have to disable some.
                     limits: if cfg!(target_arch = "wasm32") {
                         wgpu::Limits::downlevel_webgl2_defaults()
-                    } else {
+                    } else if wgpu::Limits::default().check_limits(&adapter.limits()) {
                         wgpu::Limits::default()
+                    } else {
+                        // software adapters tend to fall short of the defaults
+                        wgpu::Limits::downlevel_defaults().using_resolution(adapter.limits())
                     },
                     label: None,
                 },
                 None, // Trace path
             )
             .await
-            .unwrap();
+            .context("could not get a device from the adapter")?;
 
         let surface_caps = surface.get_capabilities(&adapter);
 
@@ -157,123 +198,53 @@ impl State {
             view_formats: vec![],
         };
         surface.configure(&device, &config);
-        let res = 3.0;
-
-        let page0 = doc.load_page(0).unwrap();
-        let pixmap0 = page0
-            .to_pixmap(
-                &Matrix::new_scale(res, res),
-                &mupdf::Colorspace::device_rgb(),
-                1.0,
-                false,
-            )
-            .unwrap();
-        let pixels: &[u8] = bytemuck::cast_slice(pixmap0.pixels().unwrap());
-        let diffuse_texture = texture::Texture::from_bytes(
-            &device,
-            &queue,
-            pixels,
-            pixmap0.width(),
-            pixmap0.height(),
-            "page0",
-        )
-        .unwrap();
-
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        // This should match the filterable field of the
-                        // corresponding Texture entry above.
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-                label: Some("texture_bind_group_layout"),
-            });
-
-        let diffuse_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },
-            ],
-            label: Some("diffuse_bind_group"),
-        });
-
-        let shader = device.create_shader_module(wgpu::include_wgsl!("./shader.wgsl"));
-
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[&texture_bind_group_layout],
-                push_constant_ranges: &[],
-            });
+        // wgpu has no callback for a lost device. a reset or removed gpu
+        // shows up as running out of memory, which a new device gets out
+        // of too; anything else is reported and the frame goes on
+        device.on_uncaptured_error(Box::new(move |error| {
+            match error {
+                wgpu::Error::OutOfMemory { .. } => {
+                    eprintln!("gpu out of memory, rebuilding");
+                    sink.uncaptured.lost.store(true, Ordering::Relaxed);
+                }
+                wgpu::Error::Validation { description, .. } => {
+                    eprintln!("wgpu error: {}", description);
+                    sink.uncaptured.errors.lock().unwrap().push(description);
+                }
+            }
+            let _ = sink.proxy.send_event(());
+        }));
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[Vertex::desc()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-        });
+        Ok(Self {
+            surface,
+            device: Arc::new(device),
+            queue: Arc::new(queue),
+            config,
+        })
+    }
+}
 
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
-            contents: bytemuck::cast_slice(SQUARE),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
-        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Index Buffer"),
-            contents: bytemuck::cast_slice(SQUARE_INDICES),
-            usage: wgpu::BufferUsages::INDEX,
-        });
-        let num_indices = SQUARE_INDICES.len() as u32;
+impl State {
+    // Generate a new State from a window, the gpu made for it and the
+    // viewer drawing into it, the command line and the doc's page labels
+    fn new(
+        window: Window,
+        gpu: Gpu,
+        viewer: Viewer,
+        sink: ErrorSink,
+        args: Args,
+        labels: PageLabels,
+        user_config: Config,
+    ) -> Self {
+        let size = window.inner_size();
+        let outline = Toc::load(viewer.document());
+        let ui = Ui::new(&window, &gpu.device, gpu.config.format);
+        let Gpu {
+            surface,
+            device,
+            queue,
+            config,
+        } = gpu;
 
         Self {
             //graphics data
@@ -283,59 +254,243 @@ impl State {
             queue,
             config,
             size,
-            render_pipeline,
-            vertex_buffer,
-            index_buffer,
-            num_indices,
-            diffuse_bind_group,
-            diffuse_texture,
+            viewer,
+            ui,
             //internal data
-            doc,
-            page: 0,
+            filename: args.filename,
             toc: false,
-            pos: Point { x: 0.0, y: 0.0 },
-            res,
-            color: wgpu::Color {
-                r: 0.0,
-                g: 0.0,
-                b: 0.0,
-                a: 0.0,
-            },
+            outline,
+            headings: None,
+            labels,
+            keys: KeyParser::new(),
+            notice: None,
+            user_config,
+            watcher: None,
+            shader_errors: Vec::new(),
+            post_errors: Vec::new(),
+            gpu_errors: Vec::new(),
+            backend: args.backend,
+            sink,
+            surface_lost: false,
         }
     }
 
     // simply return the window
     fn window(&self) -> &Window {
-        return &self.window;
+        &self.window
     }
 
     // handle resizing
     fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        // a minimized window has no surface to configure
+        if new_size.width == 0 || new_size.height == 0 {
+            return;
+        }
         self.size = new_size;
         self.config.width = new_size.width;
         self.config.height = new_size.height;
         self.surface.configure(&self.device, &self.config);
+        self.viewer.resize(new_size);
+    }
+
+    fn device_lost(&self) -> bool {
+        self.sink.uncaptured.lost.load(Ordering::Relaxed)
+    }
+
+    // the device is gone; draw on a new one
+    fn lose_device(&self) {
+        self.sink.uncaptured.lost.store(true, Ordering::Relaxed);
+        self.window.request_redraw();
+    }
+
+    // take in what the error handler left, to be shown until dismissed.
+    // an error made every frame is shown once
+    fn take_gpu_errors(&mut self) {
+        let errors = std::mem::take(&mut *self.sink.uncaptured.errors.lock().unwrap());
+        for error in errors {
+            if !self.gpu_errors.contains(&error) {
+                self.gpu_errors.push(error);
+                self.viewer.request_redraw();
+            }
+        }
+    }
+
+    // start over on a new device. the viewer keeps the page, view and
+    // camera, and rasterizes pages again as they are needed
+    fn rebuild(&mut self) -> Result<()> {
+        self.sink.uncaptured.lost.store(false, Ordering::Relaxed);
+        self.surface_lost = false;
+        let gpu = pollster::block_on(Gpu::new(
+            &self.window,
+            self.size,
+            self.backend,
+            self.sink.clone(),
+        ))?;
+        self.ui = Ui::new(&self.window, &gpu.device, gpu.config.format);
+        self.viewer
+            .set_device(gpu.device.clone(), gpu.queue.clone(), gpu.config.format);
+        self.surface = gpu.surface;
+        self.device = gpu.device;
+        self.queue = gpu.queue;
+        self.config = gpu.config;
+        Ok(())
+    }
+
+    // the monitor changed; actual-size pages have to follow it
+    fn rescale(&mut self, scale_factor: f64, new_size: winit::dpi::PhysicalSize<u32>) {
+        self.viewer.rescale(scale_factor, new_size);
+        self.resize(new_size);
+    }
+
+    // switch between `view` and single pages, keeping the page
+    fn toggle_view(&mut self, view: View) {
+        let view = self.viewer.toggle_view(view);
+        self.notice = Some(format!("view: {}", view.name()));
     }
+
+    fn toggle_cover(&mut self) {
+        let cover = self.viewer.toggle_cover();
+        self.notice = Some(format!("cover page: {}", on_off(cover)));
+    }
+
+    fn toggle_rtl(&mut self) {
+        let rtl = self.viewer.toggle_rtl();
+        self.notice = Some(format!("right to left: {}", on_off(rtl)));
+    }
+
+    fn toggle_annotations(&mut self) {
+        let annotations = self.viewer.toggle_annotations();
+        self.notice = Some(format!("annotations: {}", on_off(annotations)));
+    }
+
+    fn cycle_theme(&mut self) {
+        let theme = self.viewer.cycle_theme();
+        self.notice = Some(format!("theme: {}", theme.name()));
+    }
+
+    fn cycle_vision(&mut self) {
+        let vision = self.viewer.cycle_vision();
+        self.notice = Some(format!("vision: {}", vision.name()));
+    }
+
+    // compile the config's post-processing passes, showing whatever
+    // can't be read or doesn't compile
+    fn load_post(&mut self) {
+        let (sources, mut errors) = self.user_config.post_sources();
+        errors.extend(self.viewer.set_post(&sources));
+        self.post_errors = errors;
+    }
+
+    // draw pages with shader.wgsl as it is in the source tree. a shader
+    // that doesn't compile leaves the one before it drawing
+    fn load_shader(&mut self) {
+        let name = SHADER;
+        let result = std::fs::read_to_string(name)
+            .with_context(|| format!("could not read {}", name))
+            .and_then(|source| self.viewer.set_shader(name, &source));
+        self.shader_errors = match result {
+            Ok(()) => Vec::new(),
+            Err(e) => vec![format!("{:#}", e)],
+        };
+    }
+
+    // follow shader files as they are edited, from now on
+    fn watch_shaders(&mut self, wake: impl Fn() + Send + 'static) {
+        let mut paths = vec![PathBuf::from(SHADER)];
+        paths.extend(self.user_config.post.iter().cloned());
+        self.watcher = Some(Watcher::spawn(paths, wake));
+    }
+
+    // reload whatever shaders changed since the last frame
+    fn reload_shaders(&mut self) {
+        let Some(watcher) = &self.watcher else {
+            return;
+        };
+        let changed: Vec<PathBuf> = std::iter::from_fn(|| watcher.try_recv()).collect();
+        if changed.is_empty() {
+            return;
+        }
+        if changed.iter().any(|path| path == Path::new(SHADER)) {
+            self.load_shader();
+        }
+        if changed.iter().any(|path| path != Path::new(SHADER)) {
+            self.load_post();
+        }
+        let failed = !self.shader_errors.is_empty() || !self.post_errors.is_empty();
+        let notice = if failed {
+            "shader errors"
+        } else {
+            "shaders reloaded"
+        };
+        self.notice = Some(notice.to_string());
+        self.viewer.request_redraw();
+    }
+
+    fn cycle_fit(&mut self) {
+        let fit = self.viewer.cycle_fit();
+        self.notice = Some(format!("fit: {}", fit.name()));
+    }
+
     // whether there's an input event
     fn input(&mut self, event: &WindowEvent) -> bool {
-        match event {
-            WindowEvent::CursorMoved { position, .. } => {
-                self.color = wgpu::Color {
-                    r: position.x / self.size.width as f64,
-                    g: position.y / self.size.width as f64,
-                    b: 1.0,
-                    a: 1.0,
-                };
-                return true;
-            }
-            _ => {
-                return false;
+        if self.ui.on_event(event) {
+            return true;
+        }
+        let used = self.viewer.input(event);
+        self.take_events();
+        used
+    }
+
+    // ask for the frames something on screen is waiting for: egui's next
+    // one, on top of whatever the viewer wants
+    fn schedule(&mut self) {
+        if let Some(at) = self.ui.repaint_at() {
+            self.viewer.request_redraw_at(at);
+        }
+    }
+
+    // what the viewer did on its own: links to other documents or the
+    // web aren't followed, only shown
+    fn take_events(&mut self) {
+        for event in self.viewer.events() {
+            match event {
+                ViewerEvent::LinkClicked(Link::Uri(uri)) => {
+                    self.notice = Some(format!("link: {}", uri));
+                    self.viewer.request_redraw();
+                }
+                // the overlay reads the page every frame
+                ViewerEvent::LinkClicked(Link::Page(_)) | ViewerEvent::PageChanged(_) => {}
             }
         }
     }
 
     fn update(&mut self) {
-        //todo!()
+        self.viewer.update();
+        let view = UiView {
+            page: self.viewer.page(),
+            page_count: self.viewer.page_count(),
+            labels: &self.labels,
+            toc: if self.toc { Some(&mut self.outline) } else { None },
+            notice: self.notice.as_deref(),
+            pending: self.keys.pending(),
+            errors: self
+                .shader_errors
+                .iter()
+                .chain(&self.post_errors)
+                .chain(&self.gpu_errors)
+                .collect(),
+        };
+        match self.ui.run(&self.window, view) {
+            Some(Action::Go(cmd)) => self.viewer.execute(cmd),
+            Some(Action::ExportOutline) => self.export_outline(),
+            Some(Action::DismissErrors) => {
+                self.shader_errors.clear();
+                self.post_errors.clear();
+                self.gpu_errors.clear();
+            }
+            None => {}
+        }
+        self.take_events();
     }
 
     fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
@@ -348,30 +503,14 @@ impl State {
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                 label: Some("Render Encoder"),
             });
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(self.color),
-                        store: true,
-                    },
-                })],
-                depth_stencil_attachment: None,
-            });
-
-            let buf = &self.vertex_buffer;
-            let idxbuf = &self.index_buffer;
-            let numidx = self.num_indices;
-
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, buf.slice(..));
-            render_pass.set_index_buffer(idxbuf.slice(..), wgpu::IndexFormat::Uint16);
-            render_pass.draw_indexed(0..numidx, 0, 0..1);
-        }
+        self.viewer.draw(&mut encoder, &view);
+        self.ui.paint(
+            &self.device,
+            &self.queue,
+            &mut encoder,
+            &view,
+            [self.config.width, self.config.height],
+        );
 
         // submit will accept anything that implements IntoIter
         self.queue.submit(std::iter::once(encoder.finish()));
@@ -380,107 +519,226 @@ impl State {
         Ok(())
     }
 
-    fn update_page_texture(&mut self) {
-        let page = self.doc.load_page(self.page).unwrap();
-        let mut pixmap = page
-            .to_pixmap(
-                &Matrix::new_scale(self.res, self.res),
-                &mupdf::Colorspace::device_rgb(),
-                1.0,
-                false,
-            )
-            .unwrap();
-        pixmap.set_resolution(pixmap.resolution().0 * 10, pixmap.resolution().1 * 10);
-        let pixels: &[u8] = bytemuck::cast_slice(pixmap.pixels().unwrap());
+    // show or hide the outline, selecting the current section when shown.
+    // documents without an outline get a generated one the first time
+    fn toggle_toc(&mut self) {
+        self.toc = !self.toc;
+        let missing = self.outline.is_empty() && !self.outline.is_generated();
+        if self.toc && missing && self.headings.is_none() {
+            let proxy = self.sink.proxy.clone();
+            self.headings = Some(headings::spawn(self.filename.clone(), move || {
+                let _ = proxy.send_event(());
+            }));
+            self.notice = Some("generating outline…".to_string());
+        }
+        if self.toc {
+            if let Some(i) = self.outline.current(self.viewer.page()) {
+                self.outline.reveal(i);
+            }
+        }
+    }
 
-        let new_texture = texture::Texture::from_bytes(
+    // take the generated outline once it is ready
+    fn take_headings(&mut self) {
+        let Some(rx) = &self.headings else {
+            return;
+        };
+        let entries = match rx.try_recv() {
+            Ok(entries) => entries,
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => Err(anyhow!("the outline thread stopped")),
+        };
+        self.headings = None;
+        // left without one, so showing the outline again tries again
+        let entries = match entries {
+            Ok(entries) => entries,
+            Err(e) => {
+                self.notice = Some(format!("could not generate an outline: {:#}", e));
+                self.viewer.request_redraw();
+                return;
+            }
+        };
+        self.outline = Toc::generated(entries);
+        if self.toc {
+            if let Some(i) = self.outline.current(self.viewer.page()) {
+                self.outline.reveal(i);
+            }
+        }
+        self.notice = Some(format!("outline: {} headings found", self.outline.entries().len()));
+        self.viewer.request_redraw();
+    }
+
+    // write the outline next to the document as <name>.outline.json
+    fn export_outline(&mut self) {
+        let path = std::path::Path::new(&self.filename).with_extension("outline.json");
+        let result = headings::to_json(self.outline.entries())
+            .map_err(std::io::Error::from)
+            .and_then(|json| std::fs::write(&path, json));
+        self.notice = Some(match result {
+            Ok(()) => format!("outline saved to {}", path.display()),
+            Err(e) => format!("could not export outline: {}", e),
+        });
+    }
+
+    // draw the pages as they are on screen, without the overlay, into
+    // <name>.<page>.png next to the document
+    fn export_png(&mut self) {
+        let path = std::path::Path::new(&self.filename)
+            .with_extension(format!("{}.png", self.viewer.page() + 1));
+        let target = Offscreen::new(
             &self.device,
-            &self.queue,
-            pixels,
-            pixmap.width(),
-            pixmap.height(),
-            "newpage",
-        )
-        .unwrap();
-        self.diffuse_texture = new_texture;
-        let texture_bind_group_layout =
-            self.device
-                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                    entries: &[
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 0,
-                            visibility: wgpu::ShaderStages::FRAGMENT,
-                            ty: wgpu::BindingType::Texture {
-                                multisampled: false,
-                                view_dimension: wgpu::TextureViewDimension::D2,
-                                sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            },
-                            count: None,
-                        },
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 1,
-                            visibility: wgpu::ShaderStages::FRAGMENT,
-                            // This should match the filterable field of the
-                            // corresponding Texture entry above.
-                            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                            count: None,
-                        },
-                    ],
-                    label: Some("texture_bind_group_layout"),
-                });
-
-        self.diffuse_bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&self.diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&self.diffuse_texture.sampler),
-                },
-            ],
-            label: Some("diffuse_bind_group"),
+            self.config.width,
+            self.config.height,
+            self.config.format,
+        );
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Export Encoder"),
+            });
+        self.viewer.draw_pages(&mut encoder, target.view());
+        self.queue.submit(std::iter::once(encoder.finish()));
+        let result = target.save_png(&self.device, &self.queue, &path);
+        self.notice = Some(match result {
+            Ok(()) => format!("page saved to {}", path.display()),
+            Err(e) => format!("could not export page: {:#}", e),
         });
-        return;
+    }
+
+    // arrow keys and enter drive the outline while it is open
+    fn outline_key(&mut self, kc: VirtualKeyCode) -> bool {
+        match kc {
+            VirtualKeyCode::Up => self.outline.move_selection(-1),
+            VirtualKeyCode::Down => self.outline.move_selection(1),
+            VirtualKeyCode::Left => self.outline.collapse_selected(),
+            VirtualKeyCode::Right => self.outline.expand_selected(),
+            VirtualKeyCode::Return => {
+                if let Some(page) = self.outline.selected_page() {
+                    self.viewer.execute(Command::Goto(page));
+                }
+            }
+            _ => return false,
+        }
+        true
     }
 }
 
 pub async fn run() {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() != 2 {
-        Err::<(), &str>("Please provide the file name").unwrap();
-    }
-    let filename = args.last().take().unwrap().to_string();
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    let config = match Config::load(args.config.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            std::process::exit(2);
+        }
+    };
     let prettyname = {
-        let path = std::path::Path::new(&filename);
+        let path = std::path::Path::new(&args.filename);
         String::from(path.file_name().unwrap().to_str().unwrap())
     };
 
-    let doc = Document::open(filename.as_str()).unwrap();
-    let pixmap = doc
-        .load_page(0)
-        .unwrap()
-        .to_pixmap(
-            &Matrix::IDENTITY,
-            &mupdf::Colorspace::device_rgb(),
-            1.0,
-            false,
-        )
-        .unwrap();
+    let doc = match Doc::open(args.filename.as_str()) {
+        Ok(doc) => doc,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            std::process::exit(1);
+        }
+    };
+    let labels = doc.labels();
+    // the window opens at the first page's size in points
+    let bounds = match doc.load_page(0).and_then(|page| page.bounds()) {
+        Ok(bounds) => bounds,
+        Err(e) => {
+            eprintln!("could not read the first page: {}", e);
+            std::process::exit(1);
+        }
+    };
 
     let event_loop = EventLoop::new();
+    // finished rasters wake the event loop to be drawn
+    let proxy = event_loop.create_proxy();
+    let watch_proxy = event_loop.create_proxy();
+    let sink = ErrorSink {
+        uncaptured: Arc::new(Uncaptured::default()),
+        proxy: event_loop.create_proxy(),
+    };
+    let wake = move || {
+        let _ = proxy.send_event(());
+    };
     let window = WindowBuilder::new()
-        .with_title(format!("{}", prettyname))
+        .with_title(prettyname)
         .with_inner_size(winit::dpi::PhysicalSize::new(
-            pixmap.width(),
-            pixmap.height(),
+            bounds.width().round() as u32,
+            bounds.height().round() as u32,
         ))
         .build(&event_loop)
         .unwrap();
 
-    let mut state = State::new(window, doc).await;
+    let gpu = match args.backend {
+        Backend::Cpu => None,
+        backend => match Gpu::new(&window, window.inner_size(), backend, sink.clone()).await {
+            Ok(gpu) => Some(gpu),
+            Err(e) => {
+                eprintln!("{:#}, drawing on the cpu instead", e);
+                None
+            }
+        },
+    };
+    let Some(gpu) = gpu else {
+        #[cfg(feature = "cpu")]
+        {
+            let rasterizer = Rasterizer::spawn(args.filename.clone(), wake);
+            cpu::run(event_loop, window, args, doc, labels, rasterizer);
+        }
+        #[cfg(not(feature = "cpu"))]
+        {
+            eprintln!("no usable gpu, and the cpu fallback is not built in (--features cpu)");
+            std::process::exit(1);
+        }
+    };
+    let settings = Settings {
+        fit: args.fit,
+        view: args.view,
+        cover: args.cover,
+        rtl: args.rtl,
+        background: args.background,
+        theme: args.theme,
+        vision: args.vision,
+        cache_bytes: args.cache_mb * 1024 * 1024,
+        downsample: args.downsample,
+        scale_factor: window.scale_factor(),
+    };
+    let viewer = match Viewer::new(
+        gpu.device.clone(),
+        gpu.queue.clone(),
+        gpu.config.format,
+        &args.filename,
+        window.inner_size(),
+        settings,
+        wake,
+    ) {
+        Ok(viewer) => viewer,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            std::process::exit(1);
+        }
+    };
+    let watch = args.watch_shaders;
+    let mut state = State::new(window, gpu, viewer, sink, args, labels, config);
+    state.load_post();
+    if watch {
+        // what is on disk may be newer than what was built in
+        state.load_shader();
+        state.watch_shaders(move || {
+            let _ = watch_proxy.send_event(());
+        });
+    }
 
     event_loop.run(move |event, _, control_flow| {
         control_flow.set_wait();
@@ -488,58 +746,147 @@ pub async fn run() {
             Event::WindowEvent {
                 ref event,
                 window_id,
-            } if window_id == state.window().id() && !state.input(event) => match event {
-                WindowEvent::Resized(physical_size) => {
-                    state.resize(*physical_size);
+            } if window_id == state.window().id() && !state.input(event) => {
+                // keys can change anything on screen, if only the count
+                // being typed into the status line
+                if matches!(
+                    event,
+                    WindowEvent::KeyboardInput { .. } | WindowEvent::ReceivedCharacter(_)
+                ) {
+                    state.viewer.request_redraw();
                 }
-                WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                    // new_inner_size is &&mut so we have to dereference it twice
-                    state.resize(**new_inner_size);
-                }
-                WindowEvent::CloseRequested => {
-                    control_flow.set_exit();
-                }
-                WindowEvent::KeyboardInput {
-                    input:
-                        KeyboardInput {
-                            state: ElementState::Pressed,
-                            virtual_keycode: kc,
-                            ..
-                        },
-                    ..
-                } => match kc {
-                    Some(VirtualKeyCode::Escape) => {
+                match event {
+                    WindowEvent::Resized(physical_size) => {
+                        state.resize(*physical_size);
+                    }
+                    WindowEvent::ScaleFactorChanged {
+                        scale_factor,
+                        new_inner_size,
+                    } => {
+                        // new_inner_size is &&mut so we have to dereference it twice
+                        state.rescale(*scale_factor, **new_inner_size);
+                    }
+                    WindowEvent::CloseRequested => {
                         control_flow.set_exit();
                     }
-                    Some(VirtualKeyCode::Down) => {
-                        state.page += 1;
-                        state.update_page_texture();
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: kc,
+                                ..
+                            },
+                        ..
+                    } => match kc {
+                        Some(VirtualKeyCode::Escape) => {
+                            // escape first cancels a half-typed count
+                            if state.keys.pending().is_some() {
+                                state.keys.clear();
+                            } else {
+                                control_flow.set_exit();
+                            }
+                        }
+                        Some(kc) if state.toc && state.outline_key(*kc) => {}
+                        Some(kc) => {
+                            if let Some(cmd) = state.keys.key(*kc) {
+                                state.viewer.execute(cmd);
+                            }
+                        }
+                        None => {}
+                    },
+                    WindowEvent::ReceivedCharacter(':') => {
+                        state.ui.open_prompt();
+                    }
+                    WindowEvent::ReceivedCharacter('t') => {
+                        state.toggle_toc();
+                    }
+                    WindowEvent::ReceivedCharacter('+' | '=') => {
+                        state.viewer.zoom_center(1.25);
+                    }
+                    WindowEvent::ReceivedCharacter('-') => {
+                        state.viewer.zoom_center(0.8);
+                    }
+                    WindowEvent::ReceivedCharacter('c') => {
+                        state.toggle_view(View::Continuous);
+                    }
+                    WindowEvent::ReceivedCharacter('d') => {
+                        state.toggle_view(View::Spread);
+                    }
+                    WindowEvent::ReceivedCharacter('b') => {
+                        state.toggle_cover();
+                    }
+                    WindowEvent::ReceivedCharacter('r') => {
+                        state.toggle_rtl();
                     }
-                    Some(VirtualKeyCode::Up) => {
-                        state.page -= 1;
-                        state.update_page_texture();
+                    WindowEvent::ReceivedCharacter('a') => {
+                        state.toggle_annotations();
+                    }
+                    WindowEvent::ReceivedCharacter('p') => {
+                        state.export_png();
+                    }
+                    WindowEvent::ReceivedCharacter('i') => {
+                        state.cycle_theme();
+                    }
+                    WindowEvent::ReceivedCharacter('v') => {
+                        state.cycle_vision();
+                    }
+                    WindowEvent::ReceivedCharacter('f') => {
+                        state.cycle_fit();
+                    }
+                    WindowEvent::ReceivedCharacter('z') => {
+                        state.viewer.reset_zoom();
+                    }
+                    WindowEvent::ReceivedCharacter(c) => {
+                        if let Some(cmd) = state.keys.char(*c) {
+                            state.viewer.execute(cmd);
+                        }
                     }
                     _ => {}
-                },
-                _ => {}
-            },
+                }
+            }
             Event::MainEventsCleared => {
-                state.window.request_redraw();
+                state.reload_shaders();
+                state.take_gpu_errors();
+                state.take_headings();
+                state.schedule();
+                if state.device_lost() || state.viewer.due(Instant::now()) {
+                    state.window.request_redraw();
+                }
+            }
+            Event::RedrawEventsCleared => {
+                // sleep until the next frame asked for, or the next event
+                state.schedule();
+                if let Some(wake) = state.viewer.wake() {
+                    control_flow.set_wait_until(wake);
+                }
             }
             Event::RedrawRequested(window_id) if window_id == state.window().id() => {
                 // Redraw the application.
 
+                if state.device_lost() {
+                    if let Err(e) = state.rebuild() {
+                        eprintln!("could not rebuild the gpu state: {:#}", e);
+                        control_flow.set_exit();
+                        return;
+                    }
+                }
                 state.update();
                 match state.render() {
-                    Ok(_) => {}
-                    Err(wgpu::SurfaceError::Timeout) => state.resize(state.size),
-                    Err(wgpu::SurfaceError::OutOfMemory) => {
-                        eprintln!("Out of memory. Exiting.");
-                        control_flow.set_exit();
+                    Ok(_) => state.surface_lost = false,
+                    // the frame is skipped; try again with a fresh surface
+                    Err(wgpu::SurfaceError::Outdated) => state.resize(state.size),
+                    Err(wgpu::SurfaceError::Timeout) => state.viewer.request_redraw(),
+                    // configuring the surface again is usually enough
+                    Err(wgpu::SurfaceError::Lost) if !state.surface_lost => {
+                        state.surface_lost = true;
+                        state.resize(state.size);
+                        state.window().request_redraw();
                     }
-                    Err(e) => {
-                        eprint!("{:?}", e);
-                        control_flow.set_exit();
+                    // the surface went with the device, or the device ran
+                    // out of memory; start over on a new one
+                    Err(e @ (wgpu::SurfaceError::Lost | wgpu::SurfaceError::OutOfMemory)) => {
+                        eprintln!("{}, rebuilding", e);
+                        state.lose_device();
                     }
                 };
             }
@@ -547,3 +894,11 @@ pub async fn run() {
         }
     });
 }
+
+fn on_off(on: bool) -> &'static str {
+    if on {
+        "on"
+    } else {
+        "off"
+    }
+}
@@ -0,0 +1,82 @@
+use anyhow::*;
+
+// what draws the window
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    // any gpu wgpu finds, then a software adapter, then the cpu
+    Auto,
+    Vulkan,
+    Metal,
+    Dx12,
+    Gl,
+    // wgpu's software adapter, where the platform has one
+    Fallback,
+    // no wgpu at all: pages are drawn on the cpu and put up with softbuffer
+    Cpu,
+}
+
+impl Backend {
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Auto => "auto",
+            Backend::Vulkan => "vulkan",
+            Backend::Metal => "metal",
+            Backend::Dx12 => "dx12",
+            Backend::Gl => "gl",
+            Backend::Fallback => "fallback",
+            Backend::Cpu => "cpu",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        [
+            Backend::Auto,
+            Backend::Vulkan,
+            Backend::Metal,
+            Backend::Dx12,
+            Backend::Gl,
+            Backend::Fallback,
+            Backend::Cpu,
+        ]
+        .into_iter()
+        .filter(|&b| b != Backend::Cpu || cfg!(feature = "cpu"))
+        .find(|b| b.name() == name)
+    }
+
+    // the wgpu backends to look for adapters on
+    pub fn backends(self) -> wgpu::Backends {
+        match self {
+            Backend::Auto | Backend::Fallback => wgpu::Backends::all(),
+            Backend::Vulkan => wgpu::Backends::VULKAN,
+            Backend::Metal => wgpu::Backends::METAL,
+            Backend::Dx12 => wgpu::Backends::DX12,
+            Backend::Gl => wgpu::Backends::GL,
+            Backend::Cpu => wgpu::Backends::empty(),
+        }
+    }
+}
+
+// an adapter that can draw to `surface`. when there is no real gpu, as on
+// headless machines and some remote desktops, a software adapter is tried
+pub async fn request_adapter(
+    instance: &wgpu::Instance,
+    surface: &wgpu::Surface,
+    backend: Backend,
+) -> Result<wgpu::Adapter> {
+    let request = |force_fallback_adapter| {
+        instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::default(),
+            compatible_surface: Some(surface),
+            force_fallback_adapter,
+        })
+    };
+    if backend != Backend::Fallback {
+        if let Some(adapter) = request(false).await {
+            return Ok(adapter);
+        }
+        eprintln!("no gpu adapter for {}, trying a software one", backend.name());
+    }
+    request(true)
+        .await
+        .with_context(|| format!("no software adapter for {}", backend.name()))
+}
@@ -0,0 +1,67 @@
+use crate::page::PageSlot;
+use crate::raster::Job;
+
+// page textures kept on the gpu, least recently used first. once they
+// take more than the budget the oldest ones not on screen are dropped
+pub struct PageCache {
+    slots: Vec<PageSlot>,
+    // bytes of texture memory to stay under
+    budget: u64,
+}
+
+impl PageCache {
+    pub fn new(budget: u64) -> Self {
+        Self {
+            slots: Vec::new(),
+            budget,
+        }
+    }
+
+    pub fn get(&self, job: &Job) -> Option<&PageSlot> {
+        self.slots.iter().find(|s| s.job == *job)
+    }
+
+    pub fn contains(&self, job: &Job) -> bool {
+        self.get(job).is_some()
+    }
+
+    // the most recently used raster of `page` at any resolution or options
+    pub fn any(&self, page: i32) -> Option<&PageSlot> {
+        self.slots.iter().rev().find(|s| s.job.page == page)
+    }
+
+    // mark `job` as just used
+    pub fn touch(&mut self, job: &Job) {
+        if let Some(i) = self.slots.iter().position(|s| s.job == *job) {
+            let slot = self.slots.remove(i);
+            self.slots.push(slot);
+        }
+    }
+
+    // add `slot`, then evict down to the budget, sparing the jobs in
+    // `keep` and the new slot itself. returns what was dropped
+    pub fn insert(&mut self, slot: PageSlot, keep: &[Job]) -> Vec<PageSlot> {
+        let mut evicted = Vec::new();
+        if let Some(i) = self.slots.iter().position(|s| s.job == slot.job) {
+            evicted.push(self.slots.remove(i));
+        }
+        self.slots.push(slot);
+        let mut i = 0;
+        while self.bytes() > self.budget && i + 1 < self.slots.len() {
+            if keep.contains(&self.slots[i].job) {
+                i += 1;
+            } else {
+                evicted.push(self.slots.remove(i));
+            }
+        }
+        evicted
+    }
+
+    pub fn budget(&self) -> u64 {
+        self.budget
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.slots.iter().map(|s| s.bytes()).sum()
+    }
+}
@@ -0,0 +1,103 @@
+use std::time::{Duration, Instant};
+
+use egui_winit::winit;
+use mupdf::Point;
+
+pub const MIN_ZOOM: f32 = 0.1;
+pub const MAX_ZOOM: f32 = 32.0;
+// how long zooming must pause before the page is re-rasterized
+const ZOOM_SETTLE: Duration = Duration::from_millis(250);
+
+// view transform applied in vs_main: clip = position.xy * scale + offset
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CameraUniform {
+    scale: [f32; 2],
+    offset: [f32; 2],
+}
+
+// pan and zoom of the page, done on the gpu without re-rasterizing
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera {
+    // pan offset in clip space
+    pub pos: Point,
+    pub zoom: f32,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Camera {
+    pub fn new() -> Self {
+        Self {
+            pos: Point { x: 0.0, y: 0.0 },
+            zoom: 1.0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    pub fn uniform(&self) -> CameraUniform {
+        CameraUniform {
+            scale: [self.zoom, self.zoom],
+            offset: [self.pos.x, self.pos.y],
+        }
+    }
+
+    // move by a mouse delta in physical pixels
+    pub fn pan(&mut self, dx: f32, dy: f32, size: winit::dpi::PhysicalSize<u32>) {
+        self.pos.x += 2.0 * dx / size.width.max(1) as f32;
+        self.pos.y -= 2.0 * dy / size.height.max(1) as f32;
+    }
+
+    // zoom by `factor`, keeping the point under `cursor` (in pixels) fixed
+    pub fn zoom_at(
+        &mut self,
+        factor: f32,
+        cursor: winit::dpi::PhysicalPosition<f64>,
+        size: winit::dpi::PhysicalSize<u32>,
+    ) {
+        let zoom = (self.zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+        let ratio = zoom / self.zoom;
+        let cx = 2.0 * cursor.x as f32 / size.width.max(1) as f32 - 1.0;
+        let cy = 1.0 - 2.0 * cursor.y as f32 / size.height.max(1) as f32;
+        self.pos.x = cx - (cx - self.pos.x) * ratio;
+        self.pos.y = cy - (cy - self.pos.y) * ratio;
+        self.zoom = zoom;
+    }
+}
+
+// when the zoom last changed, until the page is re-rasterized for it.
+// rasterizing at every step of a zoom would fall behind, so pages wait
+// for it to pause
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ZoomSettle {
+    changed: Option<Instant>,
+}
+
+impl ZoomSettle {
+    pub fn changed(&mut self) {
+        self.changed = Some(Instant::now());
+    }
+
+    // when the zoom in progress settles, if there is one
+    pub fn at(&self) -> Option<Instant> {
+        self.changed.map(|at| at + ZOOM_SETTLE)
+    }
+
+    // whether the zoom has settled by now, true once per change
+    pub fn settled(&mut self) -> bool {
+        match self.at() {
+            Some(at) if Instant::now() >= at => {
+                self.changed = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use anyhow::*;
+
+use pdf_rust::{Downsample, Fit, Theme, View, Vision};
+
+use crate::backend::Backend;
+
+macro_rules! usage {
+    ($backends:literal) => {
+        concat!(
+            "usage: pdf_rust [--fit page|width|height|actual] \
+             [--continuous | --spread [--cover] [--rtl]] [--background RRGGBB] \
+             [--theme normal|dark|sepia|solarized|high-contrast] \
+             [--vision normal|protanopia|deuteranopia|tritanopia|daltonize|grayscale] [--cache MB] \
+             [--downsample box|tent] [--backend ",
+            $backends,
+            "] [--config FILE] [--watch-shaders] FILE"
+        )
+    };
+}
+
+// the cpu backend is only there when built with the cpu feature
+#[cfg(feature = "cpu")]
+const USAGE: &str = usage!("auto|vulkan|metal|dx12|gl|fallback|cpu");
+#[cfg(not(feature = "cpu"))]
+const USAGE: &str = usage!("auto|vulkan|metal|dx12|gl|fallback");
+
+// command line options
+#[derive(Clone, Debug)]
+pub struct Args {
+    pub filename: String,
+    pub fit: Fit,
+    pub view: View,
+    // spreads keep the first page alone / read right to left
+    pub cover: bool,
+    pub rtl: bool,
+    // shown around the page where it does not cover the window
+    pub background: wgpu::Color,
+    // colors the pages are drawn in
+    pub theme: Theme,
+    // simulated or corrected color vision
+    pub vision: Vision,
+    // texture memory page rasters may take on the gpu, in megabytes
+    pub cache_mb: u64,
+    // how pages shown smaller than their raster are filtered
+    pub downsample: Downsample,
+    // what to draw with, when the one wgpu picks doesn't work
+    pub backend: Backend,
+    // a config file other than the default one
+    pub config: Option<String>,
+    // reload shader.wgsl and the post passes when they change on disk
+    pub watch_shaders: bool,
+}
+
+impl Args {
+    // parse the arguments after the program name
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self> {
+        let mut filename = None;
+        let mut fit = Fit::Page;
+        let mut view = View::Single;
+        let mut cover = false;
+        let mut rtl = false;
+        let mut background = wgpu::Color::BLACK;
+        let mut theme = Theme::Normal;
+        let mut vision = Vision::Normal;
+        let mut cache_mb = 256;
+        let mut downsample = Downsample::Box;
+        let mut backend = Backend::Auto;
+        let mut config = None;
+        let mut watch_shaders = false;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--fit" => {
+                    let value = args.next().context(USAGE)?;
+                    fit = Fit::parse(&value)
+                        .with_context(|| format!("unknown fit mode '{}'\n{}", value, USAGE))?;
+                }
+                "--continuous" => view = View::Continuous,
+                "--spread" => view = View::Spread,
+                "--cover" => cover = true,
+                "--rtl" => rtl = true,
+                "--background" => {
+                    let value = args.next().context(USAGE)?;
+                    background = parse_color(&value)
+                        .with_context(|| format!("invalid color '{}'\n{}", value, USAGE))?;
+                }
+                "--theme" => {
+                    let value = args.next().context(USAGE)?;
+                    theme = Theme::parse(&value)
+                        .with_context(|| format!("unknown theme '{}'\n{}", value, USAGE))?;
+                }
+                "--vision" => {
+                    let value = args.next().context(USAGE)?;
+                    vision = Vision::parse(&value)
+                        .with_context(|| format!("unknown vision '{}'\n{}", value, USAGE))?;
+                }
+                "--cache" => {
+                    let value = args.next().context(USAGE)?;
+                    cache_mb = value
+                        .parse()
+                        .ok()
+                        .with_context(|| format!("invalid cache size '{}'\n{}", value, USAGE))?;
+                }
+                "--downsample" => {
+                    let value = args.next().context(USAGE)?;
+                    downsample = Downsample::parse(&value)
+                        .with_context(|| format!("unknown filter '{}'\n{}", value, USAGE))?;
+                }
+                "--backend" => {
+                    let value = args.next().context(USAGE)?;
+                    backend = Backend::parse(&value)
+                        .with_context(|| format!("unknown backend '{}'\n{}", value, USAGE))?;
+                }
+                "--config" => config = Some(args.next().context(USAGE)?),
+                "--watch-shaders" => watch_shaders = true,
+                "-h" | "--help" => bail!(USAGE),
+                _ if arg.starts_with("--") => bail!("unknown option '{}'\n{}", arg, USAGE),
+                _ if filename.is_none() => filename = Some(arg),
+                _ => bail!(USAGE),
+            }
+        }
+        Ok(Self {
+            filename: filename.context(USAGE)?,
+            fit,
+            view,
+            cover,
+            rtl,
+            background,
+            theme,
+            vision,
+            cache_mb,
+            downsample,
+            backend,
+            config,
+            watch_shaders,
+        })
+    }
+}
+
+// "RRGGBB" or "#RRGGBB", in srgb
+fn parse_color(value: &str) -> Option<wgpu::Color> {
+    let hex = value.strip_prefix('#').unwrap_or(value);
+    if hex.len() != 6 {
+        return None;
+    }
+    let channel = |i: usize| {
+        let v = u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()?;
+        Some(srgb_to_linear(v as f64 / 255.0))
+    };
+    Some(wgpu::Color {
+        r: channel(0)?,
+        g: channel(2)?,
+        b: channel(4)?,
+        a: 1.0,
+    })
+}
+
+// the clear color is written to an srgb surface, so it has to be linear
+fn srgb_to_linear(c: f64) -> f64 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
@@ -0,0 +1,60 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+// settings that live in a file rather than on the command line. read
+// from --config, or from $XDG_CONFIG_HOME/xj9/config.json when it exists
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    // wgsl post-processing passes run over the pages in order. relative
+    // paths are from the config file's directory
+    pub post: Vec<PathBuf>,
+}
+
+impl Config {
+    // the file at `path`, the default one, or nothing if neither is given
+    // and the default doesn't exist
+    pub fn load(path: Option<&str>) -> Result<Self> {
+        let path = match path {
+            Some(path) => PathBuf::from(path),
+            None => match default_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Self::default()),
+            },
+        };
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("could not read {}", path.display()))?;
+        let mut config: Self = serde_json::from_str(&text)
+            .with_context(|| format!("could not parse {}", path.display()))?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+        for pass in &mut config.post {
+            *pass = dir.join(&*pass);
+        }
+        Ok(config)
+    }
+
+    // the post passes' names and sources, and errors for the ones that
+    // can't be read
+    pub fn post_sources(&self) -> (Vec<(String, String)>, Vec<String>) {
+        let mut sources = Vec::new();
+        let mut errors = Vec::new();
+        for path in &self.post {
+            let name = path.display().to_string();
+            match std::fs::read_to_string(path) {
+                Ok(source) => sources.push((name, source)),
+                Err(e) => errors.push(format!("{}: {}", name, e)),
+            }
+        }
+        (sources, errors)
+    }
+}
+
+fn default_path() -> Option<PathBuf> {
+    let dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(dir.join("xj9").join("config.json"))
+}
@@ -0,0 +1,61 @@
+// Expands mupdf pixmap samples of any layout into rgba, one invocation per
+// pixel. Samples come in as bytes packed into words; output rows are padded
+// so they can be copied straight into a texture.
+
+struct Params {
+    width: u32,
+    height: u32,
+    // bytes from one input row to the next
+    stride: u32,
+    // bytes per input pixel
+    components: u32,
+    // 0 gray, 1 rgb, 2 bgr, 3 cmyk
+    colors: u32,
+    // 1 if the last component is (premultiplied) alpha
+    alpha: u32,
+    // words from one output row to the next
+    out_row: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> params: Params;
+@group(0) @binding(1)
+var<storage, read> samples: array<u32>;
+@group(0) @binding(2)
+var<storage, read_write> pixels: array<u32>;
+
+fn sample(i: u32) -> f32 {
+    return f32((samples[i / 4u] >> ((i % 4u) * 8u)) & 255u) / 255.0;
+}
+
+@compute @workgroup_size(8, 8)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= params.width || id.y >= params.height {
+        return;
+    }
+    let at = id.y * params.stride + id.x * params.components;
+    var a = 1.0;
+    if params.alpha == 1u {
+        a = sample(at + params.components - 1u);
+    }
+
+    var rgb = vec3<f32>(0.0);
+    switch params.colors {
+        case 0u: {
+            rgb = vec3<f32>(sample(at));
+        }
+        case 1u: {
+            rgb = vec3<f32>(sample(at), sample(at + 1u), sample(at + 2u));
+        }
+        case 2u: {
+            rgb = vec3<f32>(sample(at + 2u), sample(at + 1u), sample(at));
+        }
+        default: {
+            // naive cmyk, done unpremultiplied and premultiplied again
+            let cmyk = vec4<f32>(sample(at), sample(at + 1u), sample(at + 2u), sample(at + 3u));
+            let c = cmyk / max(a, 1.0 / 255.0);
+            rgb = (1.0 - min(c.xyz, vec3<f32>(1.0))) * (1.0 - min(c.w, 1.0)) * a;
+        }
+    }
+    pixels[id.y * params.out_row + id.x] = pack4x8unorm(vec4<f32>(rgb, a));
+}
@@ -0,0 +1,252 @@
+use std::num::NonZeroU32;
+
+use anyhow::{anyhow, ensure, Result};
+use egui_winit::winit::{event::*, event_loop::EventLoop, window::Window};
+
+use pdf_rust::fallback::{self, Format, Job, Options, Raster, Rasterizer, Rendered, Work};
+use pdf_rust::{Command, Fit, KeyParser};
+
+use crate::app::Doc;
+use crate::cli::Args;
+use crate::labels::PageLabels;
+
+// the viewer for when wgpu has no adapter at all: one page at a time,
+// rasterized to fit and copied into a softbuffer surface. no zoom, views
+// or overlay, just the keys for moving around
+struct CpuView {
+    // declared before the window, so they are dropped before it
+    surface: softbuffer::Surface,
+    _context: softbuffer::Context,
+    window: Window,
+    doc: Doc,
+    title: String,
+    labels: PageLabels,
+    keys: KeyParser,
+    rasterizer: Rasterizer,
+    page: i32,
+    page_count: i32,
+    fit: Fit,
+    // the page raster asked for last and the last one that came back
+    wanted: Option<Job>,
+    raster: Option<Raster>,
+    // around the page, as 0x00RRGGBB
+    background: u32,
+}
+
+impl CpuView {
+    fn new(
+        window: Window,
+        args: Args,
+        doc: Doc,
+        labels: PageLabels,
+        rasterizer: Rasterizer,
+    ) -> Result<Self> {
+        // # Safety
+        //
+        // Like the wgpu surface, these need the window to outlive them,
+        // which the field order makes sure of
+        let context = unsafe { softbuffer::Context::new(&window) }
+            .map_err(|e| anyhow!("could not start softbuffer: {}", e))?;
+        let surface = unsafe { softbuffer::Surface::new(&context, &window) }
+            .map_err(|e| anyhow!("could not create a softbuffer surface: {}", e))?;
+        let channel = |c: f64| (linear_to_srgb(c) * 255.0).round() as u32;
+        let background = (channel(args.background.r) << 16)
+            | (channel(args.background.g) << 8)
+            | channel(args.background.b);
+        let title = std::path::Path::new(&args.filename)
+            .file_name()
+            .map_or(args.filename.clone(), |name| name.to_string_lossy().into_owned());
+        let mut view = Self {
+            surface,
+            _context: context,
+            window,
+            page_count: doc.page_count()?,
+            doc,
+            title,
+            labels,
+            keys: KeyParser::new(),
+            rasterizer,
+            page: 0,
+            fit: args.fit,
+            wanted: None,
+            raster: None,
+            background,
+        };
+        view.request();
+        Ok(view)
+    }
+
+    // ask for the current page at the scale that fits it to the window
+    fn request(&mut self) {
+        let size = match self.doc.load_page(self.page).and_then(|p| p.bounds()) {
+            Ok(bounds) => (bounds.width(), bounds.height()),
+            Err(e) => {
+                eprintln!("could not load page {}: {:?}", self.page + 1, e);
+                return;
+            }
+        };
+        let window = self.window.inner_size();
+        let scale_factor = self.window.scale_factor();
+        let (w, _) = fallback::page_pixels(self.fit, size, window, scale_factor);
+        let job = Job {
+            page: self.page,
+            res: w / size.0.max(1.0),
+            options: Options::default(),
+        };
+        if self.wanted != Some(job) {
+            self.wanted = Some(job);
+            self.rasterizer.request(vec![Work::Page(job)]);
+        }
+        let label = match self.labels.label(self.page) {
+            Some(label) => format!("{} ({} / {})", label, self.page + 1, self.page_count),
+            None => format!("{} / {}", self.page + 1, self.page_count),
+        };
+        self.window.set_title(&format!("{} - {}", self.title, label));
+    }
+
+    fn execute(&mut self, cmd: Command) {
+        let page = cmd.apply(self.page, self.page_count);
+        if page != self.page {
+            self.page = page;
+            self.request();
+        }
+    }
+
+    fn receive(&mut self, rendered: Rendered) {
+        if Some(rendered.work) != self.wanted.map(Work::Page) {
+            return;
+        }
+        match rendered.raster {
+            Ok(raster) => {
+                self.raster = Some(raster);
+                self.window.request_redraw();
+            }
+            Err(e) => eprintln!("could not render page {}: {:?}", self.page + 1, e),
+        }
+    }
+
+    // copy the page over the background: centered, or from the top when
+    // it is taller than the window
+    fn draw(&mut self) -> Result<()> {
+        let size = self.window.inner_size();
+        let (Some(width), Some(height)) =
+            (NonZeroU32::new(size.width), NonZeroU32::new(size.height))
+        else {
+            return Ok(());
+        };
+        self.surface
+            .resize(width, height)
+            .map_err(|e| anyhow!("could not resize the surface: {}", e))?;
+        let mut buffer = self
+            .surface
+            .buffer_mut()
+            .map_err(|e| anyhow!("could not get the surface buffer: {}", e))?;
+        buffer.fill(self.background);
+        if let Some(raster) = &self.raster {
+            ensure!(
+                raster.format == Format::RGBA,
+                "the cpu view only draws rgba rasters"
+            );
+            let (w, h) = (size.width as i64, size.height as i64);
+            let (rw, rh) = (raster.width as i64, raster.height as i64);
+            let x0 = (w - rw) / 2;
+            let y0 = if rh > h { 0 } else { (h - rh) / 2 };
+            for y in y0.max(0)..(y0 + rh).min(h) {
+                let row = &raster.samples[((y - y0) * raster.stride as i64) as usize..];
+                for x in x0.max(0)..(x0 + rw).min(w) {
+                    let i = ((x - x0) * 4) as usize;
+                    buffer[(y * w + x) as usize] = over_white(&row[i..i + 4]);
+                }
+            }
+        }
+        buffer
+            .present()
+            .map_err(|e| anyhow!("could not present: {}", e))
+    }
+}
+
+// a premultiplied rgba pixel on white paper, as 0x00RRGGBB, like the
+// page shader does it
+fn over_white(px: &[u8]) -> u32 {
+    let paper = 255 - px[3] as u32;
+    let c = |v: u8| (v as u32 + paper).min(255);
+    (c(px[0]) << 16) | (c(px[1]) << 8) | c(px[2])
+}
+
+// the background is kept linear for the srgb surface; softbuffer wants srgb
+fn linear_to_srgb(c: f64) -> f64 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+pub fn run(
+    event_loop: EventLoop<()>,
+    window: Window,
+    args: Args,
+    doc: Doc,
+    labels: PageLabels,
+    rasterizer: Rasterizer,
+) -> ! {
+    let mut view = match CpuView::new(window, args, doc, labels, rasterizer) {
+        Ok(view) => view,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            std::process::exit(1);
+        }
+    };
+
+    event_loop.run(move |event, _, control_flow| {
+        control_flow.set_wait();
+        match event {
+            Event::WindowEvent { event, window_id } if window_id == view.window.id() => {
+                match event {
+                    WindowEvent::CloseRequested => control_flow.set_exit(),
+                    WindowEvent::Resized(_) | WindowEvent::ScaleFactorChanged { .. } => {
+                        view.request();
+                        view.window.request_redraw();
+                    }
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(kc),
+                                ..
+                            },
+                        ..
+                    } => {
+                        if kc == VirtualKeyCode::Escape {
+                            if view.keys.pending().is_some() {
+                                view.keys.clear();
+                            } else {
+                                control_flow.set_exit();
+                            }
+                        } else if let Some(cmd) = view.keys.key(kc) {
+                            view.execute(cmd);
+                        }
+                    }
+                    WindowEvent::ReceivedCharacter(c) => {
+                        if let Some(cmd) = view.keys.char(c) {
+                            view.execute(cmd);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Event::UserEvent(()) => {
+                while let Some(rendered) = view.rasterizer.try_recv() {
+                    view.receive(rendered);
+                }
+            }
+            Event::RedrawRequested(window_id) if window_id == view.window.id() => {
+                if let Err(e) = view.draw() {
+                    eprintln!("{:#}", e);
+                    control_flow.set_exit();
+                }
+            }
+            _ => {}
+        }
+    })
+}
@@ -0,0 +1,407 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use anyhow::{Context, Result};
+use mupdf::text_page::TextBlockType;
+use mupdf::TextPageOptions;
+use serde::Serialize;
+
+use crate::outline::Entry;
+
+// a text block that looks like it could be a heading
+#[derive(Clone, Debug)]
+struct Candidate {
+    page: i32,
+    text: String,
+    // largest glyph size in the block, rounded to half points
+    size: f32,
+    // depth implied by a "3.2"-style number, if the text starts with one
+    numbered: Option<usize>,
+}
+
+// headings that are neither numbered nor this much larger than body text are ignored
+const SIZE_RATIO: f32 = 1.15;
+// at most this many distinct font sizes become outline levels
+const MAX_SIZE_LEVELS: usize = 3;
+// blocks longer than this are paragraphs, not headings
+const MAX_HEADING_CHARS: usize = 120;
+
+// a text block as read off a page, before it is judged
+#[derive(Clone, Debug)]
+struct Block {
+    page: i32,
+    // its lines, trimmed and joined with spaces
+    text: String,
+    // the size of every glyph in it
+    sizes: Vec<f32>,
+}
+
+// build an outline for documents that have none, from structured text.
+// glyph weight is not exposed by the mupdf bindings, so headings are
+// recognised by size relative to body text and by section numbering
+pub fn generate(doc: &mupdf::Document) -> Vec<Entry> {
+    outline(&read_blocks(doc))
+}
+
+// `generate` for `filename` on a thread of its own, since reading every
+// page of a long document takes a while. `wake` is called once the
+// outline, or why there is none, can be taken from the receiver
+pub fn spawn(filename: String, wake: impl Fn() + Send + 'static) -> Receiver<Result<Vec<Entry>>> {
+    let (tx, rx) = mpsc::channel();
+    thread::Builder::new()
+        .name("headings".into())
+        .spawn(move || {
+            let entries = mupdf::Document::open(&filename)
+                .with_context(|| format!("could not open {}", filename))
+                .map(|doc| generate(&doc));
+            if tx.send(entries).is_ok() {
+                wake();
+            }
+        })
+        .expect("could not start the headings thread");
+    rx
+}
+
+fn read_blocks(doc: &mupdf::Document) -> Vec<Block> {
+    let page_count = doc.page_count().unwrap_or(0);
+    let mut blocks = Vec::new();
+    for page in 0..page_count {
+        let Ok(text_page) = doc
+            .load_page(page)
+            .and_then(|p| p.to_text_page(TextPageOptions::empty()))
+        else {
+            continue;
+        };
+        for block in text_page.blocks() {
+            if block.r#type() != TextBlockType::Text {
+                continue;
+            }
+            let mut lines = Vec::new();
+            let mut sizes = Vec::new();
+            for line in block.lines() {
+                let mut text = String::new();
+                for c in line.chars() {
+                    sizes.push(c.size());
+                    if let Some(c) = c.char() {
+                        text.push(c);
+                    }
+                }
+                lines.push(text.trim().to_string());
+            }
+            blocks.push(Block {
+                page,
+                text: lines.join(" "),
+                sizes,
+            });
+        }
+    }
+    blocks
+}
+
+// pick the headings out of a document's blocks
+fn outline(blocks: &[Block]) -> Vec<Entry> {
+    let mut candidates = Vec::new();
+    // glyph count per rounded size, to find the body text size
+    let mut sizes: HashMap<i32, usize> = HashMap::new();
+    for block in blocks {
+        for &size in &block.sizes {
+            *sizes.entry(half_points(size)).or_default() += 1;
+        }
+        if looks_like_heading(&block.text) {
+            let size = block.sizes.iter().copied().fold(0.0, f32::max);
+            candidates.push(Candidate {
+                page: block.page,
+                numbered: numbering_depth(&block.text),
+                text: block.text.clone(),
+                size: half_points(size) as f32 / 2.0,
+            });
+        }
+    }
+
+    let body = sizes
+        .into_iter()
+        .max_by_key(|&(_, count)| count)
+        .map_or(0.0, |(size, _)| size as f32 / 2.0);
+    let candidates = drop_running_heads(candidates);
+
+    // the largest few heading sizes become levels 0, 1, 2...
+    let mut levels: Vec<f32> = candidates
+        .iter()
+        .map(|c| c.size)
+        .filter(|&s| s >= body * SIZE_RATIO)
+        .collect();
+    levels.sort_by(|a, b| b.total_cmp(a));
+    levels.dedup();
+    levels.truncate(MAX_SIZE_LEVELS);
+
+    let mut entries: Vec<Entry> = Vec::new();
+    for c in candidates {
+        let by_size = levels.iter().position(|&l| l == c.size);
+        let depth = match (c.numbered, by_size) {
+            (Some(depth), _) => depth,
+            (None, Some(level)) => level,
+            (None, None) => continue,
+        };
+        // keep the tree well formed: never skip a level on the way down
+        let max_depth = entries.last().map_or(0, |e| e.depth + 1);
+        entries.push(Entry {
+            title: c.text,
+            page: Some(c.page),
+            depth: depth.min(max_depth),
+        });
+    }
+    entries
+}
+
+fn half_points(size: f32) -> i32 {
+    (size * 2.0).round() as i32
+}
+
+fn looks_like_heading(text: &str) -> bool {
+    let len = text.chars().count();
+    (2..=MAX_HEADING_CHARS).contains(&len)
+        && text.chars().any(char::is_alphabetic)
+        && !text.ends_with(['.', ',', ';', ':'])
+}
+
+// "3" -> 0, "3.2" -> 1, "3.2.1" -> 2, also "A.1" appendix numbering;
+// the number must be followed by a capitalised title
+fn numbering_depth(text: &str) -> Option<usize> {
+    let (number, title) = text.split_once(char::is_whitespace)?;
+    let number = number.strip_suffix('.').unwrap_or(number);
+    let parts: Vec<&str> = number.split('.').collect();
+    let first = parts[0];
+    let first_ok = first.parse::<u32>().is_ok_and(|n| n < 100)
+        || (parts.len() > 1 && first.len() == 1 && first.chars().all(|c| c.is_ascii_uppercase()));
+    let rest_ok = parts[1..]
+        .iter()
+        .all(|p| p.parse::<u32>().is_ok_and(|n| n < 100));
+    let title_ok = title
+        .trim_start()
+        .chars()
+        .next()
+        .is_some_and(char::is_uppercase);
+    if first_ok && rest_ok && title_ok && parts.len() <= 4 {
+        Some(parts.len() - 1)
+    } else {
+        None
+    }
+}
+
+// running headers and footers repeat on many pages; real headings do not
+fn drop_running_heads(candidates: Vec<Candidate>) -> Vec<Candidate> {
+    let mut pages_per_text: HashMap<&str, HashSet<i32>> = HashMap::new();
+    for c in &candidates {
+        pages_per_text
+            .entry(c.text.as_str())
+            .or_default()
+            .insert(c.page);
+    }
+    let repeated: Vec<String> = pages_per_text
+        .into_iter()
+        .filter(|(_, pages)| pages.len() > 2)
+        .map(|(text, _)| text.to_string())
+        .collect();
+    candidates
+        .into_iter()
+        .filter(|c| !repeated.contains(&c.text))
+        .collect()
+}
+
+// nested form of the outline, as written by `to_json`
+#[derive(Serialize)]
+struct Node<'a> {
+    title: &'a str,
+    // one-based, like the page numbers people read
+    page: Option<i32>,
+    children: Vec<Node<'a>>,
+}
+
+// serialise a flattened outline as a nested json tree
+pub fn to_json(entries: &[Entry]) -> serde_json::Result<String> {
+    let (nodes, _) = nest(entries, 0, 0);
+    serde_json::to_string_pretty(&nodes)
+}
+
+// build the nodes at `depth` starting from `start`, returning where they stop
+fn nest(entries: &[Entry], start: usize, depth: usize) -> (Vec<Node<'_>>, usize) {
+    let mut nodes = Vec::new();
+    let mut i = start;
+    while i < entries.len() && entries[i].depth >= depth {
+        let entry = &entries[i];
+        let (children, next) = nest(entries, i + 1, entry.depth + 1);
+        nodes.push(Node {
+            title: &entry.title,
+            page: entry.page.map(|p| p + 1),
+            children,
+        });
+        i = next;
+    }
+    (nodes, i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `text` set in `size` points on `page`
+    fn block(page: i32, text: &str, size: f32) -> Block {
+        Block {
+            page,
+            text: text.to_string(),
+            sizes: vec![size; text.chars().count()],
+        }
+    }
+
+    // enough 10 point text on `page` to make that the body size
+    fn body(page: i32) -> Block {
+        block(page, &"Lorem ipsum dolor sit amet. ".repeat(20), 10.0)
+    }
+
+    fn entry(title: &str, page: i32, depth: usize) -> Entry {
+        Entry {
+            title: title.to_string(),
+            page: Some(page),
+            depth,
+        }
+    }
+
+    fn titles(entries: &[Entry]) -> Vec<(&str, Option<i32>, usize)> {
+        entries
+            .iter()
+            .map(|e| (e.title.as_str(), e.page, e.depth))
+            .collect()
+    }
+
+    #[test]
+    fn headings_by_size() {
+        let blocks = [
+            block(0, "A Title", 20.0),
+            body(0),
+            block(1, "Background", 14.0),
+            body(1),
+            // barely larger than the body
+            block(1, "Aside", 10.5),
+            // large, but a sentence
+            block(2, "This is a pull quote.", 14.0),
+            block(2, "Method", 14.0),
+            body(2),
+        ];
+        assert_eq!(
+            titles(&outline(&blocks)),
+            [
+                ("A Title", Some(0), 0),
+                ("Background", Some(1), 1),
+                ("Method", Some(2), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn levels_never_skip_on_the_way_down() {
+        let blocks = [
+            block(0, "Part", 24.0),
+            block(0, "Subsection", 12.0),
+            block(0, "Chapter", 18.0),
+            block(1, "Subsection again", 12.0),
+            body(0),
+        ];
+        assert_eq!(
+            titles(&outline(&blocks)),
+            [
+                ("Part", Some(0), 0),
+                ("Subsection", Some(0), 1),
+                ("Chapter", Some(0), 1),
+                ("Subsection again", Some(1), 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn numbering() {
+        assert_eq!(numbering_depth("3 Results"), Some(0));
+        assert_eq!(numbering_depth("3. Results"), Some(0));
+        assert_eq!(numbering_depth("3.2 Method"), Some(1));
+        assert_eq!(numbering_depth("3.2.1 Data sets"), Some(2));
+        assert_eq!(numbering_depth("A.1 Proofs"), Some(1));
+        assert_eq!(numbering_depth("3 results"), None);
+        assert_eq!(numbering_depth("2023 Annual Report"), None);
+        assert_eq!(numbering_depth("A Title"), None);
+        assert_eq!(numbering_depth("1.2.3.4.5 Too deep"), None);
+        assert_eq!(numbering_depth("Results"), None);
+    }
+
+    #[test]
+    fn numbered_headings_at_body_size() {
+        let blocks = [
+            block(0, "1 Introduction", 10.0),
+            body(0),
+            block(1, "1.1 Motivation", 10.0),
+            block(1, "1.1.1 History", 10.0),
+            block(2, "2 Design", 10.0),
+            body(2),
+        ];
+        assert_eq!(
+            titles(&outline(&blocks)),
+            [
+                ("1 Introduction", Some(0), 0),
+                ("1.1 Motivation", Some(1), 1),
+                ("1.1.1 History", Some(1), 2),
+                ("2 Design", Some(2), 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn running_heads_are_dropped() {
+        let mut blocks = Vec::new();
+        for page in 0..4 {
+            blocks.push(block(page, "Journal of Things", 14.0));
+            blocks.push(body(page));
+        }
+        blocks.push(block(2, "Findings", 14.0));
+        // on two pages only, so still a heading
+        blocks.push(block(1, "Notes", 14.0));
+        blocks.push(block(3, "Notes", 14.0));
+        // three times, but on two pages
+        blocks.push(block(3, "Notes", 14.0));
+        assert_eq!(
+            titles(&outline(&blocks)),
+            [
+                ("Findings", Some(2), 0),
+                ("Notes", Some(1), 0),
+                ("Notes", Some(3), 0),
+                ("Notes", Some(3), 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn json_nests_by_depth() {
+        let entries = [
+            entry("One", 0, 0),
+            entry("One.A", 1, 1),
+            entry("One.A.i", 1, 2),
+            entry("One.B", 2, 1),
+            entry("Two", 4, 0),
+        ];
+        let json: serde_json::Value = serde_json::from_str(&to_json(&entries).unwrap()).unwrap();
+        let expected = serde_json::json!([
+            {
+                "title": "One",
+                "page": 1,
+                "children": [
+                    {
+                        "title": "One.A",
+                        "page": 2,
+                        "children": [{ "title": "One.A.i", "page": 2, "children": [] }],
+                    },
+                    { "title": "One.B", "page": 3, "children": [] },
+                ],
+            },
+            { "title": "Two", "page": 5, "children": [] },
+        ]);
+        assert_eq!(json, expected);
+    }
+}
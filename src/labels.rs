@@ -0,0 +1,290 @@
+use mupdf::pdf::{PdfDocument, PdfObject};
+
+// numbering style of a page label range, from the /S entry
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Style {
+    None,
+    Decimal,
+    UpperRoman,
+    LowerRoman,
+    UpperAlpha,
+    LowerAlpha,
+}
+
+// a run of pages sharing a prefix and numbering style
+#[derive(Clone, Debug)]
+struct Range {
+    // physical index of the first page in the range
+    start: i32,
+    style: Style,
+    prefix: String,
+    // the number given to the first page in the range
+    first: i32,
+}
+
+// the document's /PageLabels, mapping physical pages to printed labels
+#[derive(Clone, Debug, Default)]
+pub struct PageLabels {
+    ranges: Vec<Range>,
+}
+
+impl PageLabels {
+    // read the labels from an open pdf; documents without any get an
+    // empty table
+    pub fn load(pdf: &PdfDocument) -> Self {
+        let ranges = read_ranges(pdf).unwrap_or_default();
+        Self { ranges }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    // the printed label of a zero-based page, if the document defines one
+    pub fn label(&self, page: i32) -> Option<String> {
+        let range = self.ranges.iter().rev().find(|r| r.start <= page)?;
+        let n = range.first + (page - range.start);
+        let number = match range.style {
+            Style::None => String::new(),
+            Style::Decimal => n.to_string(),
+            Style::UpperRoman => roman(n),
+            Style::LowerRoman => roman(n).to_lowercase(),
+            Style::UpperAlpha => alpha(n),
+            Style::LowerAlpha => alpha(n).to_lowercase(),
+        };
+        Some(format!("{}{}", range.prefix, number))
+    }
+
+    // resolve user input to a zero-based page.
+    // labels win over physical numbers; a leading '#' forces a physical number
+    pub fn resolve(&self, input: &str, page_count: i32) -> Option<i32> {
+        let input = input.trim();
+        if let Some(physical) = input.strip_prefix('#') {
+            return physical_page(physical, page_count);
+        }
+        if !self.is_empty() {
+            let exact = (0..page_count).find(|&p| self.label(p).as_deref() == Some(input));
+            let page = exact.or_else(|| {
+                (0..page_count).find(|&p| {
+                    self.label(p)
+                        .is_some_and(|l| l.eq_ignore_ascii_case(input))
+                })
+            });
+            if page.is_some() {
+                return page;
+            }
+        }
+        physical_page(input, page_count)
+    }
+}
+
+// a one-based physical page number as typed by the user
+fn physical_page(input: &str, page_count: i32) -> Option<i32> {
+    let n: i32 = input.trim().parse().ok()?;
+    if n >= 1 && n <= page_count {
+        Some(n - 1)
+    } else {
+        None
+    }
+}
+
+fn read_ranges(pdf: &PdfDocument) -> Option<Vec<Range>> {
+    let catalog = pdf.catalog().ok()?;
+    let tree = resolved(catalog.get_dict("PageLabels").ok()??)?;
+    let mut ranges = Vec::new();
+    walk_number_tree(&tree, &mut ranges, 0);
+    ranges.sort_by_key(|r| r.start);
+    Some(ranges)
+}
+
+// follow an indirect reference, leaving direct objects untouched
+fn resolved(obj: PdfObject) -> Option<PdfObject> {
+    if obj.is_indirect().ok()? {
+        obj.resolve().ok()?
+    } else {
+        Some(obj)
+    }
+}
+
+// collect the /Nums entries of a number tree node and all of its /Kids
+fn walk_number_tree(node: &PdfObject, ranges: &mut Vec<Range>, depth: u32) {
+    // guard against reference cycles in broken files
+    if depth > 32 {
+        return;
+    }
+    if let Some(nums) = node.get_dict("Nums").ok().flatten().and_then(resolved) {
+        let len = nums.len().unwrap_or(0) as i32;
+        for i in (0..len - 1).step_by(2) {
+            let start = nums.get_array(i).ok().flatten().and_then(|k| k.as_int().ok());
+            let dict = nums.get_array(i + 1).ok().flatten().and_then(resolved);
+            if let (Some(start), Some(dict)) = (start, dict) {
+                ranges.push(read_range(start, &dict));
+            }
+        }
+    }
+    if let Some(kids) = node.get_dict("Kids").ok().flatten().and_then(resolved) {
+        let len = kids.len().unwrap_or(0) as i32;
+        for i in 0..len {
+            if let Some(kid) = kids.get_array(i).ok().flatten().and_then(resolved) {
+                walk_number_tree(&kid, ranges, depth + 1);
+            }
+        }
+    }
+}
+
+fn read_range(start: i32, dict: &PdfObject) -> Range {
+    let style = match dict
+        .get_dict("S")
+        .ok()
+        .flatten()
+        .and_then(|s| s.as_name().ok().map(|n| n.to_vec()))
+        .as_deref()
+    {
+        Some(b"D") => Style::Decimal,
+        Some(b"R") => Style::UpperRoman,
+        Some(b"r") => Style::LowerRoman,
+        Some(b"A") => Style::UpperAlpha,
+        Some(b"a") => Style::LowerAlpha,
+        _ => Style::None,
+    };
+    let prefix = dict
+        .get_dict("P")
+        .ok()
+        .flatten()
+        .and_then(|p| p.as_string().ok().map(String::from))
+        .unwrap_or_default();
+    let first = dict
+        .get_dict("St")
+        .ok()
+        .flatten()
+        .and_then(|s| s.as_int().ok())
+        .unwrap_or(1);
+    Range {
+        start,
+        style,
+        prefix,
+        first,
+    }
+}
+
+fn roman(mut n: i32) -> String {
+    const DIGITS: &[(i32, &str)] = &[
+        (1000, "M"),
+        (900, "CM"),
+        (500, "D"),
+        (400, "CD"),
+        (100, "C"),
+        (90, "XC"),
+        (50, "L"),
+        (40, "XL"),
+        (10, "X"),
+        (9, "IX"),
+        (5, "V"),
+        (4, "IV"),
+        (1, "I"),
+    ];
+    let mut out = String::new();
+    for &(value, digits) in DIGITS {
+        while n >= value {
+            out.push_str(digits);
+            n -= value;
+        }
+    }
+    out
+}
+
+// pdf letter numbering: A..Z, then AA..ZZ, then AAA..ZZZ and so on
+fn alpha(n: i32) -> String {
+    if n < 1 {
+        return String::new();
+    }
+    let letter = char::from(b'A' + ((n - 1) % 26) as u8);
+    let times = usize::try_from((n - 1) / 26 + 1).unwrap_or(1);
+    letter.to_string().repeat(times)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: i32, style: Style, prefix: &str, first: i32) -> Range {
+        Range {
+            start,
+            style,
+            prefix: prefix.to_string(),
+            first,
+        }
+    }
+
+    // a book with roman front matter, arabic body and lettered appendices
+    fn book() -> PageLabels {
+        PageLabels {
+            ranges: vec![
+                range(0, Style::LowerRoman, "", 1),
+                range(4, Style::Decimal, "", 1),
+                range(10, Style::UpperAlpha, "App. ", 1),
+            ],
+        }
+    }
+
+    #[test]
+    fn roman_numerals() {
+        assert_eq!(roman(1), "I");
+        assert_eq!(roman(4), "IV");
+        assert_eq!(roman(9), "IX");
+        assert_eq!(roman(14), "XIV");
+        assert_eq!(roman(40), "XL");
+        assert_eq!(roman(1994), "MCMXCIV");
+        assert_eq!(roman(0), "");
+    }
+
+    #[test]
+    fn alpha_numbering() {
+        assert_eq!(alpha(1), "A");
+        assert_eq!(alpha(26), "Z");
+        assert_eq!(alpha(27), "AA");
+        assert_eq!(alpha(53), "AAA");
+        assert_eq!(alpha(0), "");
+    }
+
+    #[test]
+    fn styles_prefixes_and_starts() {
+        let labels = PageLabels {
+            ranges: vec![
+                range(0, Style::LowerRoman, "", 3),
+                range(2, Style::UpperRoman, "", 1),
+                range(3, Style::LowerAlpha, "", 2),
+                range(4, Style::UpperAlpha, "", 1),
+                range(5, Style::Decimal, "S-", 10),
+                range(7, Style::None, "Cover", 1),
+            ],
+        };
+        let all: Vec<_> = (0..8).map(|p| labels.label(p).unwrap()).collect();
+        assert_eq!(all, ["iii", "iv", "I", "b", "A", "S-10", "S-11", "Cover"]);
+        assert_eq!(PageLabels::default().label(0), None);
+    }
+
+    #[test]
+    fn resolves_labels_before_physical_numbers() {
+        let labels = book();
+        assert_eq!(labels.resolve("iii", 12), Some(2));
+        assert_eq!(labels.resolve("III", 12), Some(2));
+        // "1" is the body's first page, not the first sheet
+        assert_eq!(labels.resolve("1", 12), Some(4));
+        assert_eq!(labels.resolve("App. B", 12), Some(11));
+        // numbers no label uses are physical pages
+        assert_eq!(labels.resolve("9", 12), Some(8));
+        assert_eq!(labels.resolve("13", 12), None);
+        assert_eq!(labels.resolve("nope", 12), None);
+    }
+
+    #[test]
+    fn hash_forces_a_physical_page() {
+        let labels = book();
+        assert_eq!(labels.resolve("#1", 12), Some(0));
+        assert_eq!(labels.resolve(" #12 ", 12), Some(11));
+        assert_eq!(labels.resolve("#0", 12), None);
+        assert_eq!(labels.resolve("#13", 12), None);
+        assert_eq!(PageLabels::default().resolve("3", 12), Some(2));
+    }
+}
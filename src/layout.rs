@@ -0,0 +1,416 @@
+use egui_winit::winit;
+
+// how a page is sized against the window
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fit {
+    // the whole page is visible
+    Page,
+    Width,
+    Height,
+    // the page's printed size on this monitor
+    Actual,
+}
+
+impl Fit {
+    pub fn next(self) -> Self {
+        match self {
+            Fit::Page => Fit::Width,
+            Fit::Width => Fit::Height,
+            Fit::Height => Fit::Actual,
+            Fit::Actual => Fit::Page,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Fit::Page => "page",
+            Fit::Width => "width",
+            Fit::Height => "height",
+            Fit::Actual => "actual",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        [Fit::Page, Fit::Width, Fit::Height, Fit::Actual]
+            .into_iter()
+            .find(|f| f.name() == name)
+    }
+}
+
+// a rectangle in clip space, y pointing up
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quad {
+    pub left: f32,
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+}
+
+// pdf points are 1/72 inch, a scale factor of 1 means 96 pixels per inch
+const PIXELS_PER_POINT: f64 = 96.0 / 72.0;
+
+// size in physical pixels of a page of `page` points under `fit`
+pub fn page_pixels(
+    fit: Fit,
+    page: (f32, f32),
+    window: winit::dpi::PhysicalSize<u32>,
+    scale_factor: f64,
+) -> (f32, f32) {
+    let (pw, ph) = (page.0.max(1.0), page.1.max(1.0));
+    let (ww, wh) = (window.width.max(1) as f32, window.height.max(1) as f32);
+    let scale = match fit {
+        Fit::Page => (ww / pw).min(wh / ph),
+        Fit::Width => ww / pw,
+        Fit::Height => wh / ph,
+        Fit::Actual => (PIXELS_PER_POINT * scale_factor) as f32,
+    };
+    (pw * scale, ph * scale)
+}
+
+// place a page of `size` pixels in the window: centered, except that
+// pages taller than the window start with their top edge showing
+pub fn place(size: (f32, f32), window: winit::dpi::PhysicalSize<u32>) -> Quad {
+    let half_w = size.0 / window.width.max(1) as f32;
+    let half_h = size.1 / window.height.max(1) as f32;
+    let top = if half_h > 1.0 { 1.0 } else { half_h };
+    Quad {
+        left: -half_w,
+        top,
+        right: half_w,
+        bottom: top - 2.0 * half_h,
+    }
+}
+
+// how pages are arranged in the window
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum View {
+    // one page at a time
+    Single,
+    // all pages stacked top to bottom, scrolled smoothly
+    Continuous,
+    // two pages side by side, like an open book
+    Spread,
+}
+
+impl View {
+    pub fn name(self) -> &'static str {
+        match self {
+            View::Single => "single",
+            View::Continuous => "continuous",
+            View::Spread => "spread",
+        }
+    }
+}
+
+// gap between stacked pages, in physical pixels
+const GAP: f32 = 12.0;
+
+// the pages of a continuous view stacked into one tall column,
+// measured in physical pixels at zoom 1
+#[derive(Clone, Debug, Default)]
+pub struct Strip {
+    // top edge and pixel size of each page
+    tops: Vec<f32>,
+    sizes: Vec<(f32, f32)>,
+}
+
+impl Strip {
+    pub fn new(
+        fit: Fit,
+        page_sizes: &[(f32, f32)],
+        window: winit::dpi::PhysicalSize<u32>,
+        scale_factor: f64,
+    ) -> Self {
+        let mut tops = Vec::with_capacity(page_sizes.len());
+        let mut sizes = Vec::with_capacity(page_sizes.len());
+        let mut y = GAP;
+        for &page in page_sizes {
+            let size = page_pixels(fit, page, window, scale_factor);
+            tops.push(y);
+            sizes.push(size);
+            y += size.1 + GAP;
+        }
+        Self { tops, sizes }
+    }
+
+    pub fn height(&self) -> f32 {
+        match (self.tops.last(), self.sizes.last()) {
+            (Some(top), Some(size)) => top + size.1 + GAP,
+            _ => 0.0,
+        }
+    }
+
+    pub fn top(&self, page: i32) -> f32 {
+        self.tops.get(page.max(0) as usize).copied().unwrap_or(0.0)
+    }
+
+    pub fn page_height(&self, page: i32) -> f32 {
+        self.sizes.get(page.max(0) as usize).map_or(0.0, |s| s.1)
+    }
+
+    // the page at height `y`, counting a gap as part of the page below it
+    pub fn page_at(&self, y: f32) -> i32 {
+        let below = self.tops.partition_point(|&top| top - GAP <= y);
+        below.saturating_sub(1) as i32
+    }
+
+    // pages overlapping the band from `y0` to `y1`
+    pub fn visible(&self, y0: f32, y1: f32) -> std::ops::Range<i32> {
+        let first = self.page_at(y0);
+        let last = self.page_at(y1);
+        first..last + 1
+    }
+
+    // where `page` lands in clip space when the strip is scrolled to `scroll`
+    pub fn quad(&self, page: i32, scroll: f32, window: winit::dpi::PhysicalSize<u32>) -> Quad {
+        let (w, h) = self.sizes[page as usize];
+        let (ww, wh) = (window.width.max(1) as f32, window.height.max(1) as f32);
+        let top = 1.0 - 2.0 * (self.top(page) - scroll) / wh;
+        Quad {
+            left: -w / ww,
+            top,
+            right: w / ww,
+            bottom: top - 2.0 * h / wh,
+        }
+    }
+}
+
+// how pages pair up into spreads. with a cover the first page stands
+// alone and the rest pair as 1-2, 3-4, ..., otherwise as 0-1, 2-3, ...
+#[derive(Clone, Copy, Debug)]
+pub struct Spreads {
+    pub cover: bool,
+    pub page_count: i32,
+}
+
+impl Spreads {
+    // the spread `page` belongs to
+    pub fn index(&self, page: i32) -> i32 {
+        match self.cover {
+            true if page <= 0 => 0,
+            true => (page + 1) / 2,
+            false => page / 2,
+        }
+    }
+
+    // the first page of spread `index`
+    pub fn start(&self, index: i32) -> i32 {
+        match self.cover {
+            true if index <= 0 => 0,
+            true => 2 * index - 1,
+            false => 2 * index,
+        }
+    }
+
+    pub fn count(&self) -> i32 {
+        if self.page_count <= 0 {
+            return 0;
+        }
+        self.index(self.page_count - 1) + 1
+    }
+
+    // the pages of spread `index`, one or two of them
+    pub fn pages(&self, index: i32) -> std::ops::Range<i32> {
+        let start = self.start(index);
+        let end = if self.cover && index == 0 { 1 } else { start + 2 };
+        start..end.min(self.page_count)
+    }
+}
+
+// place the pages of a spread side by side, fitting the pair as a whole.
+// both pages share one scale so text matches across the gutter, and
+// `rtl` puts the first page on the right
+pub fn place_spread(
+    fit: Fit,
+    pages: &[(f32, f32)],
+    window: winit::dpi::PhysicalSize<u32>,
+    scale_factor: f64,
+    rtl: bool,
+) -> Vec<Quad> {
+    let width: f32 = pages.iter().map(|p| p.0.max(1.0)).sum();
+    let height = pages.iter().map(|p| p.1.max(1.0)).fold(1.0, f32::max);
+    let size = page_pixels(fit, (width, height), window, scale_factor);
+    let scale = size.0 / width;
+    let bounds = place(size, window);
+    let (ww, wh) = (window.width.max(1) as f32, window.height.max(1) as f32);
+
+    let mut left = bounds.left;
+    let mut quads: Vec<Quad> = pages
+        .iter()
+        .map(|&(pw, ph)| {
+            let w = 2.0 * pw.max(1.0) * scale / ww;
+            let h = 2.0 * ph.max(1.0) * scale / wh;
+            // shorter pages are centered against the taller one
+            let top = bounds.top - (bounds.top - bounds.bottom - h) / 2.0;
+            let quad = Quad {
+                left,
+                top,
+                right: left + w,
+                bottom: top - h,
+            };
+            left += w;
+            quad
+        })
+        .collect();
+    if rtl {
+        // mirror the row so the first page ends up on the right
+        for quad in quads.iter_mut() {
+            let (l, r) = (quad.left, quad.right);
+            quad.left = bounds.left + bounds.right - r;
+            quad.right = bounds.left + bounds.right - l;
+        }
+    }
+    quads
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(width: u32, height: u32) -> winit::dpi::PhysicalSize<u32> {
+        winit::dpi::PhysicalSize::new(width, height)
+    }
+
+    fn quad(left: f32, top: f32, right: f32, bottom: f32) -> Quad {
+        Quad {
+            left,
+            top,
+            right,
+            bottom,
+        }
+    }
+
+    #[test]
+    fn fit_modes_scale_the_page() {
+        let page = (100.0, 200.0);
+        let window = window(400, 400);
+        assert_eq!(page_pixels(Fit::Page, page, window, 1.0), (200.0, 400.0));
+        assert_eq!(page_pixels(Fit::Width, page, window, 1.0), (400.0, 800.0));
+        assert_eq!(page_pixels(Fit::Height, page, window, 1.0), (200.0, 400.0));
+        // 96 dpi at a scale factor of 1.5 is two pixels per point
+        assert_eq!(page_pixels(Fit::Actual, page, window, 1.5), (200.0, 400.0));
+        let mut fit = Fit::Page;
+        for _ in 0..4 {
+            assert_eq!(Fit::parse(fit.name()), Some(fit));
+            fit = fit.next();
+        }
+        assert_eq!(fit, Fit::Page);
+    }
+
+    #[test]
+    fn tall_pages_start_at_the_top() {
+        assert_eq!(
+            place((200.0, 400.0), window(400, 400)),
+            quad(-0.5, 1.0, 0.5, -1.0)
+        );
+        assert_eq!(
+            place((200.0, 200.0), window(400, 400)),
+            quad(-0.5, 0.5, 0.5, -0.5)
+        );
+        assert_eq!(
+            place((200.0, 800.0), window(400, 400)),
+            quad(-0.5, 1.0, 0.5, -3.0)
+        );
+    }
+
+    #[test]
+    fn strip_stacks_pages_with_gaps() {
+        let strip = Strip::new(
+            Fit::Width,
+            &[(100.0, 100.0), (100.0, 200.0)],
+            window(200, 400),
+            1.0,
+        );
+        assert_eq!(strip.top(0), GAP);
+        assert_eq!(strip.top(1), GAP + 200.0 + GAP);
+        assert_eq!(strip.height(), GAP + 200.0 + GAP + 400.0 + GAP);
+        assert_eq!(strip.page_height(1), 400.0);
+        // the gap above a page belongs to it
+        assert_eq!(strip.page_at(0.0), 0);
+        assert_eq!(strip.page_at(211.0), 0);
+        assert_eq!(strip.page_at(212.0), 1);
+        assert_eq!(strip.page_at(10_000.0), 1);
+        assert_eq!(strip.visible(100.0, 300.0), 0..2);
+        // scrolled so the second page's top is at the window's
+        let second = strip.quad(1, strip.top(1), window(200, 400));
+        assert_eq!(second, quad(-1.0, 1.0, 1.0, -1.0));
+    }
+
+    #[test]
+    fn empty_strip() {
+        let strip = Strip::new(Fit::Page, &[], window(200, 400), 1.0);
+        assert_eq!(strip.height(), 0.0);
+        assert_eq!(strip.top(-1), 0.0);
+        assert_eq!(strip.page_at(50.0), 0);
+    }
+
+    #[test]
+    fn spreads_with_a_cover() {
+        // 0 | 1 2 | 3 4
+        let odd = Spreads {
+            cover: true,
+            page_count: 5,
+        };
+        assert_eq!(odd.count(), 3);
+        assert_eq!([0, 1, 2, 3, 4].map(|p| odd.index(p)), [0, 1, 1, 2, 2]);
+        assert_eq!(odd.pages(0), 0..1);
+        assert_eq!(odd.pages(1), 1..3);
+        assert_eq!(odd.pages(2), 3..5);
+        // 0 | 1 2 | 3, the last page alone
+        let even = Spreads {
+            cover: true,
+            page_count: 4,
+        };
+        assert_eq!(even.count(), 3);
+        assert_eq!(even.pages(2), 3..4);
+        assert_eq!(even.start(2), 3);
+    }
+
+    #[test]
+    fn spreads_without_a_cover() {
+        // 0 1 | 2 3 | 4
+        let spreads = Spreads {
+            cover: false,
+            page_count: 5,
+        };
+        assert_eq!(spreads.count(), 3);
+        assert_eq!([0, 1, 2, 3, 4].map(|p| spreads.index(p)), [0, 0, 1, 1, 2]);
+        assert_eq!(spreads.pages(0), 0..2);
+        assert_eq!(spreads.pages(2), 4..5);
+        assert_eq!(spreads.start(2), 4);
+    }
+
+    #[test]
+    fn no_pages_no_spreads() {
+        for cover in [false, true] {
+            let spreads = Spreads {
+                cover,
+                page_count: 0,
+            };
+            assert_eq!(spreads.count(), 0);
+            assert!(spreads.pages(0).is_empty());
+        }
+    }
+
+    #[test]
+    fn spreads_mirror_right_to_left() {
+        // a tall and a short page fit into a 400 x 200 window at one
+        // pixel per point, the short one centered against the tall one
+        let pages = [(100.0, 200.0), (100.0, 100.0)];
+        let ltr = place_spread(Fit::Page, &pages, window(400, 200), 1.0, false);
+        assert_eq!(ltr, [quad(-0.5, 1.0, 0.0, -1.0), quad(0.0, 0.5, 0.5, -0.5)]);
+        let rtl = place_spread(Fit::Page, &pages, window(400, 200), 1.0, true);
+        assert_eq!(rtl, [quad(0.0, 1.0, 0.5, -1.0), quad(-0.5, 0.5, 0.0, -0.5)]);
+        // the last page of a document on its own stays centered
+        let single = [(100.0, 200.0)];
+        let expected = [quad(-0.25, 1.0, 0.25, -1.0)];
+        assert_eq!(
+            place_spread(Fit::Page, &single, window(400, 200), 1.0, false),
+            expected
+        );
+        assert_eq!(
+            place_spread(Fit::Page, &single, window(400, 200), 1.0, true),
+            expected
+        );
+    }
+}
@@ -0,0 +1,51 @@
+// the viewer without its window: a document drawn with wgpu into whatever
+// target the host gives it, for embedding in other tools. the viewer
+// binary is one such host
+mod cache;
+mod camera;
+mod layout;
+mod nav;
+mod page;
+mod post;
+mod raster;
+mod recolor;
+mod redraw;
+mod render;
+mod texture;
+mod tiles;
+mod viewer;
+mod wgsl;
+mod widget;
+
+pub use layout::{Fit, View};
+pub use nav::{Command, KeyParser};
+pub use recolor::{Theme, Vision};
+pub use render::Offscreen;
+pub use texture::Downsample;
+pub use viewer::{Event, Link, Settings, Viewer};
+pub use widget::PdfView;
+
+// what the binary's softbuffer fallback rasterizes and sizes pages with.
+// not part of the api
+#[cfg(feature = "cpu")]
+#[doc(hidden)]
+pub mod fallback {
+    pub use crate::layout::page_pixels;
+    pub use crate::raster::{Job, Options, Raster, Rasterizer, Rendered, Work};
+    pub use crate::texture::Format;
+}
+
+// the renderer's parts, for the golden tests and the benches. not part of
+// the api
+#[cfg(feature = "test-support")]
+#[doc(hidden)]
+pub mod test_support {
+    pub use crate::camera::Camera;
+    pub use crate::layout::{page_pixels, place};
+    pub use crate::page::PageSlot;
+    pub use crate::post::Post;
+    pub use crate::raster::{rasterize, Job, Options, Raster};
+    pub use crate::render::Renderer;
+    pub use crate::texture::{Colors, Converter, Format, Mipmapper, Pixels, Pool, Texture};
+    pub use crate::tiles::Atlas;
+}
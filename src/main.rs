@@ -1,6 +1,15 @@
 mod app;
-mod texture;
+mod backend;
+mod cli;
+mod config;
+#[cfg(feature = "cpu")]
+mod cpu;
+mod headings;
+mod labels;
+mod outline;
+mod ui;
+mod watch;
 
 fn main() {
     pollster::block_on(app::run());
-}
\ No newline at end of file
+}
@@ -0,0 +1,62 @@
+// Fills one mip level from the level above it. The viewport covers the
+// part of the level the page raster uses, so fragment (x, y) stands for
+// the 2x2 block of source texels starting at (2x, 2y).
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+}
+
+// one triangle covering the viewport
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let x = f32(i32(index & 1u) * 4 - 1);
+    let y = f32(i32(index & 2u) * 2 - 1);
+    out.clip_position = vec4<f32>(x, y, 0.0, 1.0);
+    return out;
+}
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+
+// the size of the source level. textureDimensions can't be trusted for
+// it, gl reports the whole texture's first level instead of the view's
+struct Source {
+    size: vec2<f32>,
+}
+
+@group(0) @binding(2)
+var<uniform> source: Source;
+
+// center of the source block, in source uv
+fn block_center(position: vec4<f32>) -> vec2<f32> {
+    return position.xy * 2.0 / source.size;
+}
+
+// the source level only. implicit derivatives here would pick the level
+// below, which is the one being drawn, on backends that don't clamp to
+// the view's single level (gl again)
+fn tap(uv: vec2<f32>) -> vec4<f32> {
+    return textureSampleLevel(t_source, s_source, uv, 0.0);
+}
+
+// a 2x2 box: one bilinear tap between the four texels
+@fragment
+fn fs_box(in: VertexOutput) -> @location(0) vec4<f32> {
+    return tap(block_center(in.clip_position));
+}
+
+// a 4x4 tent with weights 1 3 3 1 on each axis, from four bilinear taps
+// three quarters of a texel off the center. softer than the box, which
+// keeps thin strokes of small text from flickering in and out
+@fragment
+fn fs_tent(in: VertexOutput) -> @location(0) vec4<f32> {
+    let center = block_center(in.clip_position);
+    let d = 0.75 / source.size;
+    return (tap(center + vec2<f32>(-d.x, -d.y))
+        + tap(center + vec2<f32>(d.x, -d.y))
+        + tap(center + vec2<f32>(-d.x, d.y))
+        + tap(center + vec2<f32>(d.x, d.y))) * 0.25;
+}
@@ -0,0 +1,159 @@
+use egui_winit::winit::event::VirtualKeyCode;
+
+// a navigation request, independent of where it came from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    Next,
+    Prev,
+    First,
+    Last,
+    // zero-based page index
+    Goto(i32),
+    Forward(i32),
+    Back(i32),
+}
+
+impl Command {
+    // the page this command lands on, clamped to 0..page_count
+    pub fn apply(self, page: i32, page_count: i32) -> i32 {
+        let last = (page_count - 1).max(0);
+        let target = match self {
+            Command::Next => page.saturating_add(1),
+            Command::Prev => page.saturating_sub(1),
+            Command::First => 0,
+            Command::Last => last,
+            Command::Goto(n) => n,
+            Command::Forward(n) => page.saturating_add(n),
+            Command::Back(n) => page.saturating_sub(n),
+        };
+        target.clamp(0, last)
+    }
+}
+
+// turns keys into commands, accumulating vim-style count prefixes
+// so that `12j` moves forward twelve pages and `42G` jumps to page 42
+#[derive(Debug, Default)]
+pub struct KeyParser {
+    count: Option<i32>,
+}
+
+impl KeyParser {
+    pub fn new() -> Self {
+        Self { count: None }
+    }
+
+    // the count typed so far, if any
+    pub fn pending(&self) -> Option<i32> {
+        self.count
+    }
+
+    pub fn clear(&mut self) {
+        self.count = None;
+    }
+
+    // handle a typed character; digits extend the count
+    pub fn char(&mut self, c: char) -> Option<Command> {
+        if let Some(d) = c.to_digit(10) {
+            // a leading zero is not a count
+            if d == 0 && self.count.is_none() {
+                return None;
+            }
+            let n = self.count.unwrap_or(0);
+            self.count = Some(n.saturating_mul(10).saturating_add(d as i32));
+            return None;
+        }
+        let count = self.count.take();
+        match c {
+            'j' | ' ' => Some(count.map_or(Command::Next, Command::Forward)),
+            'k' => Some(count.map_or(Command::Prev, Command::Back)),
+            'g' => Some(count.map_or(Command::First, |n| Command::Goto(n - 1))),
+            'G' => Some(count.map_or(Command::Last, |n| Command::Goto(n - 1))),
+            _ => None,
+        }
+    }
+
+    // handle a named key that does not produce a character
+    pub fn key(&mut self, kc: VirtualKeyCode) -> Option<Command> {
+        let cmd = match kc {
+            VirtualKeyCode::Down | VirtualKeyCode::Right | VirtualKeyCode::PageDown => {
+                self.count.map_or(Command::Next, Command::Forward)
+            }
+            VirtualKeyCode::Up | VirtualKeyCode::Left | VirtualKeyCode::PageUp => {
+                self.count.map_or(Command::Prev, Command::Back)
+            }
+            VirtualKeyCode::Home => Command::First,
+            VirtualKeyCode::End => Command::Last,
+            _ => return None,
+        };
+        self.count = None;
+        Some(cmd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // what a run of typed characters ends in
+    fn typed(keys: &str) -> Option<Command> {
+        let mut parser = KeyParser::new();
+        keys.chars().filter_map(|c| parser.char(c)).last()
+    }
+
+    #[test]
+    fn clamps_to_the_document() {
+        assert_eq!(Command::Prev.apply(0, 10), 0);
+        assert_eq!(Command::Next.apply(9, 10), 9);
+        assert_eq!(Command::Goto(-3).apply(4, 10), 0);
+        assert_eq!(Command::Goto(42).apply(4, 10), 9);
+        assert_eq!(Command::Last.apply(4, 10), 9);
+        assert_eq!(Command::First.apply(4, 10), 0);
+        // an empty document stays on page zero
+        assert_eq!(Command::Next.apply(0, 0), 0);
+    }
+
+    #[test]
+    fn forward_and_back_move_by_counts() {
+        assert_eq!(Command::Forward(3).apply(2, 10), 5);
+        assert_eq!(Command::Back(3).apply(5, 10), 2);
+        assert_eq!(Command::Forward(30).apply(2, 10), 9);
+        assert_eq!(Command::Back(30).apply(5, 10), 0);
+        assert_eq!(Command::Forward(i32::MAX).apply(5, 10), 9);
+        assert_eq!(Command::Back(i32::MAX).apply(5, 10), 0);
+    }
+
+    #[test]
+    fn count_prefixes() {
+        assert_eq!(typed("12G"), Some(Command::Goto(11)));
+        assert_eq!(typed("12g"), Some(Command::Goto(11)));
+        assert_eq!(typed("3j"), Some(Command::Forward(3)));
+        assert_eq!(typed("3k"), Some(Command::Back(3)));
+        assert_eq!(typed("j"), Some(Command::Next));
+        assert_eq!(typed("G"), Some(Command::Last));
+    }
+
+    #[test]
+    fn counts_are_used_once() {
+        let mut parser = KeyParser::new();
+        assert_eq!(parser.char('4'), None);
+        assert_eq!(parser.pending(), Some(4));
+        assert_eq!(parser.key(VirtualKeyCode::Down), Some(Command::Forward(4)));
+        assert_eq!(parser.pending(), None);
+        assert_eq!(parser.char('j'), Some(Command::Next));
+    }
+
+    #[test]
+    fn invalid_counts() {
+        // a leading zero is ignored rather than starting a count
+        assert_eq!(typed("0j"), Some(Command::Next));
+        assert_eq!(typed("05j"), Some(Command::Forward(5)));
+        // a count before a key that isn't a command is dropped
+        let mut parser = KeyParser::new();
+        assert_eq!(parser.char('7'), None);
+        assert_eq!(parser.char('x'), None);
+        assert_eq!(parser.pending(), None);
+        assert_eq!(parser.char('j'), Some(Command::Next));
+        // overlong counts saturate instead of overflowing
+        assert_eq!(typed("99999999999j"), Some(Command::Forward(i32::MAX)));
+    }
+}
@@ -0,0 +1,193 @@
+// one heading of the document outline
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub title: String,
+    // zero-based target page, if the entry points into this document
+    pub page: Option<i32>,
+    pub depth: usize,
+}
+
+// the outline flattened in document order, plus the sidebar's
+// expanded/selected state so it can be driven from the keyboard
+#[derive(Debug, Default)]
+pub struct Toc {
+    entries: Vec<Entry>,
+    expanded: Vec<bool>,
+    selected: Option<usize>,
+    // set when the selection moved and the panel should scroll to it
+    scroll: bool,
+    // built by `headings::generate` rather than read from the document
+    generated: bool,
+}
+
+impl Toc {
+    // entries must be in pre-order, with children directly after their parent
+    pub fn new(entries: Vec<Entry>) -> Self {
+        let expanded = vec![false; entries.len()];
+        Self {
+            entries,
+            expanded,
+            selected: None,
+            scroll: false,
+            generated: false,
+        }
+    }
+
+    // an outline guessed from the page text, for documents without one
+    pub fn generated(entries: Vec<Entry>) -> Self {
+        Self {
+            generated: true,
+            ..Self::new(entries)
+        }
+    }
+
+    pub fn is_generated(&self) -> bool {
+        self.generated
+    }
+
+    pub fn load(doc: &mupdf::Document) -> Self {
+        let mut entries = Vec::new();
+        if let Ok(outlines) = doc.outlines() {
+            flatten(&outlines, 0, &mut entries);
+        }
+        Self::new(entries)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn has_children(&self, i: usize) -> bool {
+        self.entries
+            .get(i + 1)
+            .is_some_and(|next| next.depth > self.entries[i].depth)
+    }
+
+    pub fn is_expanded(&self, i: usize) -> bool {
+        self.expanded[i]
+    }
+
+    pub fn toggle(&mut self, i: usize) {
+        self.expanded[i] = !self.expanded[i];
+    }
+
+    fn parent(&self, i: usize) -> Option<usize> {
+        let depth = self.entries[i].depth;
+        (0..i).rev().find(|&j| self.entries[j].depth < depth)
+    }
+
+    // indices of the entries not hidden inside a collapsed parent
+    pub fn visible(&self) -> Vec<usize> {
+        let mut visible = Vec::new();
+        let mut collapsed_at: Option<usize> = None;
+        for (i, entry) in self.entries.iter().enumerate() {
+            if let Some(depth) = collapsed_at {
+                if entry.depth > depth {
+                    continue;
+                }
+                collapsed_at = None;
+            }
+            visible.push(i);
+            if self.has_children(i) && !self.expanded[i] {
+                collapsed_at = Some(entry.depth);
+            }
+        }
+        visible
+    }
+
+    // the entry whose section contains `page`: the one starting closest
+    // before it, preferring the later (deeper) entry on ties
+    pub fn current(&self, page: i32) -> Option<usize> {
+        let mut best: Option<(usize, i32)> = None;
+        for (i, entry) in self.entries.iter().enumerate() {
+            match entry.page {
+                Some(p) if p <= page && best.is_none_or(|(_, b)| p >= b) => {
+                    best = Some((i, p));
+                }
+                _ => {}
+            }
+        }
+        best.map(|(i, _)| i)
+    }
+
+    pub fn selected(&self) -> Option<usize> {
+        self.selected
+    }
+
+    pub fn selected_page(&self) -> Option<i32> {
+        self.entries[self.selected?].page
+    }
+
+    pub fn select(&mut self, i: usize) {
+        self.selected = Some(i);
+    }
+
+    // select an entry and expand its parents so it can be seen
+    pub fn reveal(&mut self, i: usize) {
+        let mut parent = self.parent(i);
+        while let Some(p) = parent {
+            self.expanded[p] = true;
+            parent = self.parent(p);
+        }
+        self.selected = Some(i);
+        self.scroll = true;
+    }
+
+    // move the selection by `delta` visible rows
+    pub fn move_selection(&mut self, delta: i32) {
+        let visible = self.visible();
+        if visible.is_empty() {
+            return;
+        }
+        let row = self
+            .selected
+            .and_then(|s| visible.iter().position(|&v| v == s));
+        let row = match row {
+            Some(row) => (row as i32 + delta).clamp(0, visible.len() as i32 - 1) as usize,
+            None => 0,
+        };
+        self.selected = Some(visible[row]);
+        self.scroll = true;
+    }
+
+    pub fn expand_selected(&mut self) {
+        if let Some(i) = self.selected {
+            if self.has_children(i) {
+                self.expanded[i] = true;
+            }
+        }
+    }
+
+    // collapse the selected entry, or step out to its parent if already collapsed
+    pub fn collapse_selected(&mut self) {
+        let Some(i) = self.selected else {
+            return;
+        };
+        if self.has_children(i) && self.expanded[i] {
+            self.expanded[i] = false;
+        } else if let Some(p) = self.parent(i) {
+            self.selected = Some(p);
+            self.scroll = true;
+        }
+    }
+
+    // whether the panel should scroll to the selection this frame
+    pub fn take_scroll(&mut self) -> bool {
+        std::mem::take(&mut self.scroll)
+    }
+}
+
+fn flatten(outlines: &[mupdf::Outline], depth: usize, entries: &mut Vec<Entry>) {
+    for outline in outlines {
+        entries.push(Entry {
+            title: outline.title.trim().to_string(),
+            page: outline.page.map(|p| p as i32),
+            depth,
+        });
+        flatten(&outline.down, depth + 1, entries);
+    }
+}
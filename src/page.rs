@@ -0,0 +1,242 @@
+use anyhow::*;
+use wgpu::util::DeviceExt;
+
+use crate::layout;
+use crate::raster::{Job, Raster};
+use crate::texture;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Vertex {
+    position: [f32; 3],
+    tex_coords: [f32; 2],
+    // the paper under the raster's transparent parts
+    bg_color: [f32; 3],
+}
+
+const SQUARE: &[Vertex] = &[
+    Vertex {
+        position: [-1.0, 1.0, 0.0],
+        tex_coords: [0.0, 0.0],
+        bg_color: [1.0, 1.0, 1.0],
+    },
+    Vertex {
+        position: [-1.0, -1.0, 0.0],
+        tex_coords: [0.0, 1.0],
+        bg_color: [1.0, 1.0, 1.0],
+    },
+    Vertex {
+        position: [1.0, -1.0, 0.0],
+        tex_coords: [1.0, 1.0],
+        bg_color: [1.0, 1.0, 1.0],
+    },
+    Vertex {
+        position: [1.0, 1.0, 0.0],
+        tex_coords: [1.0, 0.0],
+        bg_color: [1.0, 1.0, 1.0],
+    },
+];
+
+pub const SQUARE_INDICES: &[u16] = &[0, 1, 3, 1, 2, 3];
+
+// the unit square stretched over a quad in clip space
+fn quad_vertices(quad: layout::Quad) -> [Vertex; 4] {
+    let mut vertices = [SQUARE[0], SQUARE[1], SQUARE[2], SQUARE[3]];
+    for v in vertices.iter_mut() {
+        v.position[0] = if v.position[0] < 0.0 { quad.left } else { quad.right };
+        v.position[1] = if v.position[1] > 0.0 { quad.top } else { quad.bottom };
+    }
+    vertices
+}
+
+// two triangles drawing the `uv` part of a texture over `quad`, for
+// vertex buffers holding many quads. uv runs from the top left
+pub fn quad_triangles(quad: layout::Quad, uv: layout::Quad) -> [Vertex; 6] {
+    let mut corners = quad_vertices(quad);
+    for v in corners.iter_mut() {
+        v.tex_coords[0] = if v.tex_coords[0] < 0.5 { uv.left } else { uv.right };
+        v.tex_coords[1] = if v.tex_coords[1] < 0.5 { uv.top } else { uv.bottom };
+    }
+    std::array::from_fn(|i| corners[SQUARE_INDICES[i] as usize])
+}
+
+impl Vertex {
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+            ],
+        }
+    }
+}
+
+pub fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                // This should match the filterable field of the
+                // corresponding Texture entry above.
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            // the image mask, read with the same sampler
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            },
+        ],
+        label: Some("texture_bind_group_layout"),
+    })
+}
+
+pub fn bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    texture: &texture::Texture,
+    mask: &texture::Mask,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&texture.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&texture.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&mask.view),
+            },
+        ],
+        label: Some("diffuse_bind_group"),
+    })
+}
+
+// a page resident on the gpu: its raster, bind group and quad
+pub struct PageSlot {
+    // the page, raster scale and options the texture was rendered with
+    pub job: Job,
+    // page size in points
+    pub size: (f32, f32),
+    // how much of the texture the raster fills, pooled ones can be larger
+    uv: [f32; 2],
+    pub texture: texture::Texture,
+    // as large as the texture, so the same uv reads both
+    pub mask: texture::Mask,
+    pub bind_group: wgpu::BindGroup,
+    pub vertex_buffer: wgpu::Buffer,
+}
+
+impl PageSlot {
+    // upload the raster made for `job`, into a pooled texture if one fits
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        converter: &texture::Converter,
+        layout: &wgpu::BindGroupLayout,
+        pool: &mut texture::Pool,
+        job: Job,
+        raster: &Raster,
+    ) -> Result<Self> {
+        let label = format!("page{}", job.page);
+        let texture = pool.upload(device, queue, converter, &raster.pixels(), &label)?;
+        // stop half a texel short of whatever is left past the raster
+        let extent = |used: u32, full: u32| {
+            if used == full {
+                1.0
+            } else {
+                (used as f32 - 0.5) / full as f32
+            }
+        };
+        let uv = [
+            extent(raster.width, texture.texture.width()),
+            extent(raster.height, texture.texture.height()),
+        ];
+
+        let mask = match &raster.mask {
+            Some(bytes) => {
+                let (width, height) = (texture.texture.width(), texture.texture.height());
+                let mask = texture::Mask::new(device, width, height, &label);
+                mask.write(queue, bytes, raster.width, raster.height, 0, 0);
+                mask
+            }
+            None => texture::Mask::new(device, 1, 1, &label),
+        };
+
+        let bind_group = bind_group(device, layout, &texture, &mask);
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Vertex Buffer"),
+            contents: bytemuck::cast_slice(SQUARE),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+        Ok(Self {
+            job,
+            size: raster.size,
+            uv,
+            texture,
+            mask,
+            bind_group,
+            vertex_buffer,
+        })
+    }
+
+    // texture memory used, in bytes, counting every mip level
+    pub fn bytes(&self) -> u64 {
+        let texture = &self.texture.texture;
+        let pixels: u64 = (0..texture.mip_level_count())
+            .map(|level| {
+                let width = (texture.width() >> level).max(1) as u64;
+                let height = (texture.height() >> level).max(1) as u64;
+                width * height
+            })
+            .sum();
+        pixels * 4 + self.mask.bytes()
+    }
+
+    // move the page's quad
+    pub fn place(&self, queue: &wgpu::Queue, quad: layout::Quad) {
+        let mut vertices = quad_vertices(quad);
+        for v in vertices.iter_mut() {
+            v.tex_coords[0] *= self.uv[0];
+            v.tex_coords[1] *= self.uv[1];
+        }
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vertices));
+    }
+}
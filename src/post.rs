@@ -0,0 +1,332 @@
+use std::borrow::Cow;
+
+use anyhow::{bail, Result};
+
+use crate::wgsl;
+
+// post-processing passes over the drawn pages, from wgsl the user
+// supplies. each pass is a module with a fragment entry point `fs_main`
+// that gets these bindings and the uv post.wgsl's vertex stage passes on:
+//
+//     struct Post {
+//         // the target's size in pixels
+//         resolution: vec2<f32>,
+//         // the current page's size in points
+//         page_size: vec2<f32>,
+//         // the cursor in pixels from the target's top left
+//         cursor: vec2<f32>,
+//         // seconds since the viewer started
+//         time: f32,
+//     }
+//
+//     @group(0) @binding(0) var source: texture_2d<f32>;
+//     @group(0) @binding(1) var source_sampler: sampler;
+//     @group(0) @binding(2) var<uniform> post: Post;
+//
+//     @fragment
+//     fn fs_main(@location(0) uv: vec2<f32>) -> @location(0) vec4<f32>
+//
+// `source` is what the pass before drew, or the pages for the first one,
+// in linear color. the last pass draws into the target
+
+// the Post block above
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct PostUniform {
+    resolution: [f32; 2],
+    page_size: [f32; 2],
+    cursor: [f32; 2],
+    time: f32,
+    _pad: f32,
+}
+
+// index of `time` in the Post block, and where the block is bound
+const TIME_FIELD: u32 = 3;
+const UNIFORM_BINDING: u32 = 2;
+
+struct Pass {
+    pipeline: wgpu::RenderPipeline,
+}
+
+// what the pages are drawn into while there are passes, and what the
+// passes draw into before the last one
+struct Target {
+    view: wgpu::TextureView,
+    bind_group: wgpu::BindGroup,
+}
+
+pub struct Post {
+    format: wgpu::TextureFormat,
+    vertex: wgpu::ShaderModule,
+    layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    sampler: wgpu::Sampler,
+    uniform: wgpu::Buffer,
+    // names and sources of the passes, kept to load them on a new device
+    sources: Vec<(String, String)>,
+    passes: Vec<Pass>,
+    // whether any pass reads `time`, and so changes without input
+    animated: bool,
+    targets: Vec<Target>,
+    size: (u32, u32),
+}
+
+impl Post {
+    // no passes yet, for targets of `format`
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        let vertex = device.create_shader_module(wgpu::include_wgsl!("./post.wgsl"));
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: UNIFORM_BINDING,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("post_bind_group_layout"),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Post Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let uniform = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Post Buffer"),
+            size: std::mem::size_of::<PostUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Self {
+            format,
+            vertex,
+            layout,
+            pipeline_layout,
+            sampler,
+            uniform,
+            sources: Vec::new(),
+            passes: Vec::new(),
+            animated: false,
+            targets: Vec::new(),
+            size: (0, 0),
+        }
+    }
+
+    // replace the passes with `sources`, pairs of a name for errors and
+    // the wgsl, run in order. ones that don't compile are left out and
+    // come back as readable errors
+    pub fn load(&mut self, device: &wgpu::Device, sources: &[(String, String)]) -> Vec<String> {
+        self.sources = sources.to_vec();
+        self.passes.clear();
+        self.animated = false;
+        let mut errors = Vec::new();
+        for (name, source) in sources {
+            match self.compile(device, name, source) {
+                Ok((pass, animated)) => {
+                    self.passes.push(pass);
+                    self.animated |= animated;
+                }
+                Err(e) => errors.push(format!("{}: {}", name, format!("{:#}", e).trim_end())),
+            }
+        }
+        errors
+    }
+
+    pub fn sources(&self) -> &[(String, String)] {
+        &self.sources
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.passes.is_empty()
+    }
+
+    pub fn animated(&self) -> bool {
+        self.animated
+    }
+
+    fn compile(&self, device: &wgpu::Device, name: &str, source: &str) -> Result<(Pass, bool)> {
+        let module = wgsl::validate(name, source)?;
+        if !wgsl::has_entry_point(&module, "fs_main", naga::ShaderStage::Fragment) {
+            bail!("no @fragment fn fs_main");
+        }
+        let pipeline = wgsl::checked(device, || {
+            let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some(name),
+                source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(source)),
+            });
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(name),
+                layout: Some(&self.pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &self.vertex,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: self.format,
+                        blend: Some(wgpu::BlendState::REPLACE),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        })?;
+        Ok((Pass { pipeline }, reads_time(&module)))
+    }
+
+    // what the passes see of the viewer this frame
+    pub fn set_uniform(
+        &self,
+        queue: &wgpu::Queue,
+        resolution: [f32; 2],
+        page_size: [f32; 2],
+        cursor: [f32; 2],
+        time: f32,
+    ) {
+        let uniform = PostUniform {
+            resolution,
+            page_size,
+            cursor,
+            time,
+            _pad: 0.0,
+        };
+        queue.write_buffer(&self.uniform, 0, bytemuck::bytes_of(&uniform));
+    }
+
+    // make the in-between targets `width` by `height`, if they aren't
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        if self.size == (width, height) && !self.targets.is_empty() {
+            return;
+        }
+        self.size = (width, height);
+        self.targets = (0..2)
+            .map(|i| {
+                let texture = device.create_texture(&wgpu::TextureDescriptor {
+                    size: wgpu::Extent3d {
+                        width,
+                        height,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: self.format,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                        | wgpu::TextureUsages::TEXTURE_BINDING,
+                    label: Some(&format!("post_target{}", i)),
+                    view_formats: &[],
+                });
+                let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &self.layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Sampler(&self.sampler),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: UNIFORM_BINDING,
+                            resource: self.uniform.as_entire_binding(),
+                        },
+                    ],
+                    label: Some("post_bind_group"),
+                });
+                Target { view, bind_group }
+            })
+            .collect();
+    }
+
+    // where to draw the pages for `run`; after `resize`
+    pub fn input(&self) -> &wgpu::TextureView {
+        &self.targets[0].view
+    }
+
+    // run the passes from `input` through to `view`
+    pub fn run(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        for (i, pass) in self.passes.iter().enumerate() {
+            let last = i + 1 == self.passes.len();
+            let target = if last {
+                view
+            } else {
+                &self.targets[(i + 1) % 2].view
+            };
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Post Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_pipeline(&pass.pipeline);
+            render_pass.set_bind_group(0, &self.targets[i % 2].bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+}
+
+// whether any function reads the Post block's `time`
+fn reads_time(module: &naga::Module) -> bool {
+    let post = module.global_variables.iter().find_map(|(handle, global)| {
+        let binding = global.binding.as_ref()?;
+        (binding.group == 0 && binding.binding == UNIFORM_BINDING).then_some(handle)
+    });
+    let Some(post) = post else {
+        return false;
+    };
+    let functions = module
+        .functions
+        .iter()
+        .map(|(_, f)| f)
+        .chain(module.entry_points.iter().map(|e| &e.function));
+    functions.into_iter().any(|f| {
+        f.expressions.iter().any(|(_, e)| match *e {
+            naga::Expression::AccessIndex { base, index } => {
+                index == TIME_FIELD
+                    && matches!(f.expressions[base], naga::Expression::GlobalVariable(g) if g == post)
+            }
+            _ => false,
+        })
+    })
+}
@@ -0,0 +1,17 @@
+// the vertex half of every post-processing pass: one triangle covering
+// the target, with uv running from 0, 0 at the top left to 1, 1 at the
+// bottom right. the fragment half is the user's, see post.rs
+
+struct PostOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> PostOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: PostOutput;
+    out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
@@ -0,0 +1,284 @@
+use std::collections::VecDeque;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use anyhow::{Context, Result};
+use mupdf::document::Document;
+use mupdf::text_page::TextBlockType;
+use mupdf::{Device, DisplayList, IRect, Matrix, Pixmap, Rect, TextPage, TextPageOptions};
+
+use crate::texture::{Format, Pixels};
+use crate::tiles::Tile;
+
+// settings that change what a raster looks like
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Options {
+    // draw annotations and form widgets, not just the page contents
+    pub annotations: bool,
+    // find the page's images for a mask, which takes a pass over its
+    // text. only themes that recolor the page need one
+    pub images: bool,
+}
+
+// a page to rasterize at a raster scale
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Job {
+    pub page: i32,
+    pub res: f32,
+    pub options: Options,
+}
+
+// what the raster thread can be asked for: a whole page or one tile of it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Work {
+    Page(Job),
+    Tile(Tile),
+}
+
+// a finished raster: the pixmap's samples as mupdf laid them out, and
+// the page's size in points
+pub struct Raster {
+    pub size: (f32, f32),
+    pub width: u32,
+    pub height: u32,
+    pub stride: u32,
+    pub format: Format,
+    pub samples: Vec<u8>,
+    // a byte per pixel, width wide, 255 where the page shows an image.
+    // themes leave those pixels alone. None when there are no images
+    pub mask: Option<Vec<u8>>,
+}
+
+impl Raster {
+    // `images` are the page's image rects in the same device space as
+    // the pixmap, which needn't start at 0, 0
+    fn from_pixmap(size: (f32, f32), pixmap: &Pixmap, images: &[Rect]) -> Result<Self> {
+        let (width, height) = (pixmap.width(), pixmap.height());
+        let origin = (pixmap.x() as f32, pixmap.y() as f32);
+        let mut mask = None;
+        for image in images {
+            // only pixels wholly inside the image, so the page around it
+            // never keeps its paper
+            let clamp = |v: f32, max: u32| v.clamp(0.0, max as f32) as usize;
+            let x0 = clamp((image.x0 - origin.0).ceil(), width);
+            let x1 = clamp((image.x1 - origin.0).floor(), width);
+            let y0 = clamp((image.y0 - origin.1).ceil(), height);
+            let y1 = clamp((image.y1 - origin.1).floor(), height);
+            if x0 >= x1 || y0 >= y1 {
+                continue;
+            }
+            let mask = mask.get_or_insert_with(|| vec![0; (width * height) as usize]);
+            for row in mask.chunks_exact_mut(width as usize).take(y1).skip(y0) {
+                row[x0..x1].fill(255);
+            }
+        }
+        Ok(Self {
+            size,
+            width,
+            height,
+            stride: pixmap.stride() as u32,
+            format: Format::of(pixmap)?,
+            samples: pixmap.samples().to_vec(),
+            mask,
+        })
+    }
+
+    pub fn pixels(&self) -> Pixels<'_> {
+        Pixels {
+            bytes: &self.samples,
+            width: self.width,
+            height: self.height,
+            stride: self.stride,
+            format: self.format,
+        }
+    }
+}
+
+// sent back for everything the worker finishes
+pub struct Rendered {
+    pub work: Work,
+    pub raster: Result<Raster>,
+}
+
+// rasterizes pages off the event loop thread. the worker opens its own
+// copy of the document, since mupdf documents can't cross threads
+pub struct Rasterizer {
+    jobs: Sender<Vec<Work>>,
+    results: Receiver<Rendered>,
+}
+
+impl Rasterizer {
+    // `wake` is called after each raster is ready to take, to get
+    // whoever owns the event loop to come and collect it
+    pub fn spawn(filename: String, wake: impl Fn() + Send + 'static) -> Self {
+        let (jobs, rx) = mpsc::channel();
+        let (tx, results) = mpsc::channel();
+        thread::Builder::new()
+            .name("raster".into())
+            .spawn(move || work(&filename, rx, tx, wake))
+            .expect("could not start the raster thread");
+        Self { jobs, results }
+    }
+
+    // replace whatever is still queued with `jobs`, in order
+    pub fn request(&self, jobs: Vec<Work>) {
+        // the worker only goes away with the rasterizer
+        let _ = self.jobs.send(jobs);
+    }
+
+    // the next finished raster, if one is waiting
+    pub fn try_recv(&self) -> Option<Rendered> {
+        self.results.try_recv().ok()
+    }
+}
+
+fn work(filename: &str, rx: Receiver<Vec<Work>>, tx: Sender<Rendered>, wake: impl Fn()) {
+    let doc = match Document::open(filename) {
+        Ok(doc) => doc,
+        Err(e) => {
+            eprintln!("raster thread could not open {}: {:?}", filename, e);
+            return;
+        }
+    };
+    // tiles of a page come in runs, so its display list is kept around
+    let mut list = None;
+    let mut queue = VecDeque::new();
+    loop {
+        if queue.is_empty() {
+            match rx.recv() {
+                Ok(jobs) => queue = jobs.into(),
+                Err(_) => return,
+            }
+        }
+        // a newer request supersedes what is left of this one, so
+        // flipping quickly never waits on pages already scrolled past
+        while let Ok(jobs) = rx.try_recv() {
+            queue = jobs.into();
+        }
+        let Some(work) = queue.pop_front() else {
+            continue;
+        };
+        let raster = match work {
+            Work::Page(job) => rasterize(&doc, job),
+            Work::Tile(tile) => rasterize_tile(&doc, &mut list, tile),
+        };
+        if tx.send(Rendered { work, raster }).is_err() {
+            return;
+        }
+        wake();
+    }
+}
+
+pub fn rasterize(doc: &Document, job: Job) -> Result<Raster> {
+    let page = doc.load_page(job.page)?;
+    let bounds = page.bounds()?;
+    let pixmap = page.to_pixmap(
+        &Matrix::new_scale(job.res, job.res),
+        &mupdf::Colorspace::device_rgb(),
+        1.0,
+        job.options.annotations,
+    )?;
+    let images: Vec<Rect> = if job.options.images {
+        images(&page.to_text_page(TextPageOptions::PRESERVE_IMAGES)?)
+            .map(|r| scaled(r, 0.0, 0.0, job.res))
+            .collect()
+    } else {
+        Vec::new()
+    };
+    Raster::from_pixmap((bounds.width(), bounds.height()), &pixmap, &images)
+}
+
+// where a page's raster images are, in page space
+fn images(text: &TextPage) -> impl Iterator<Item = Rect> + '_ {
+    text.blocks()
+        .filter(|b| b.r#type() == TextBlockType::Image)
+        .map(|b| b.bounds())
+}
+
+// `r` moved by -x, -y and scaled, as the raster ctms do
+fn scaled(r: Rect, x: f32, y: f32, scale: f32) -> Rect {
+    Rect {
+        x0: (r.x0 - x) * scale,
+        y0: (r.y0 - y) * scale,
+        x1: (r.x1 - x) * scale,
+        y1: (r.y1 - y) * scale,
+    }
+}
+
+// a page's display list, for the page and options it was made with
+struct List {
+    page: i32,
+    annotations: bool,
+    list: DisplayList,
+    bounds: Rect,
+    // found the first time a tile asks for them
+    images: Option<Vec<Rect>>,
+}
+
+// draw just the tile's part of the page, clipped to its pixels
+fn rasterize_tile(doc: &Document, cached: &mut Option<List>, tile: Tile) -> Result<Raster> {
+    let annotations = tile.options.annotations;
+    let reuse = matches!(cached, Some(l) if l.page == tile.page && l.annotations == annotations);
+    if !reuse {
+        let page = doc.load_page(tile.page)?;
+        let list = page.to_display_list(annotations)?;
+        *cached = Some(List {
+            page: tile.page,
+            annotations,
+            list,
+            bounds: page.bounds()?,
+            images: None,
+        });
+    }
+    let cached = cached.as_mut().context("no display list")?;
+    if tile.options.images && cached.images.is_none() {
+        let text = cached.list.to_text_page(TextPageOptions::PRESERVE_IMAGES)?;
+        cached.images = Some(images(&text).collect());
+    }
+    let List {
+        list,
+        bounds,
+        images,
+        ..
+    } = cached;
+
+    let size = (bounds.width(), bounds.height());
+    let scale = tile.scale(size);
+    let [x0, y0, x1, y1] = tile.pixels(size);
+    let rect = IRect {
+        x0: x0 as i32,
+        y0: y0 as i32,
+        x1: x1 as i32,
+        y1: y1 as i32,
+    };
+    let mut pixmap = Pixmap::new_with_rect(&mupdf::Colorspace::device_rgb(), rect, true)?;
+    pixmap.clear()?;
+    {
+        // the page's top left corner lands on pixel 0, 0
+        let ctm = Matrix::new(
+            scale,
+            0.0,
+            0.0,
+            scale,
+            -bounds.x0 * scale,
+            -bounds.y0 * scale,
+        );
+        let area = Rect {
+            x0: x0 as f32,
+            y0: y0 as f32,
+            x1: x1 as f32,
+            y1: y1 as f32,
+        };
+        // dropping the device closes it, finishing the drawing
+        let device = Device::from_pixmap_with_clip(&pixmap, rect)?;
+        list.run(&device, &ctm, area)?;
+    }
+    let images: Vec<Rect> = match images {
+        Some(images) if tile.options.images => images
+            .iter()
+            .map(|&r| scaled(r, bounds.x0, bounds.y0, scale))
+            .collect(),
+        _ => Vec::new(),
+    };
+    Raster::from_pixmap(size, &pixmap, &images)
+}
@@ -0,0 +1,143 @@
+// colors pages are drawn in. the page's paper becomes the theme's paper
+// and its ink the theme's ink, with everything in between mapped along
+// the way, so switching needs no new rasters
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Theme {
+    // as the document has it
+    Normal,
+    Dark,
+    Sepia,
+    Solarized,
+    HighContrast,
+}
+
+// a last step over the drawn colors, for checking how a page reads
+// with a color vision deficiency or for correcting for one
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Vision {
+    Normal,
+    // simulations of missing long, medium and short wavelength cones
+    Protanopia,
+    Deuteranopia,
+    Tritanopia,
+    // colors shifted so deuteranopes can tell more of them apart
+    Daltonize,
+    Grayscale,
+}
+
+// paper and ink for fs_main, linear like the render target, and the
+// vision filter's number in shader.wgsl
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct RecolorUniform {
+    paper: [f32; 4],
+    ink: [f32; 4],
+    vision: u32,
+    _pad: [u32; 3],
+}
+
+impl Theme {
+    pub fn next(self) -> Self {
+        match self {
+            Theme::Normal => Theme::Dark,
+            Theme::Dark => Theme::Sepia,
+            Theme::Sepia => Theme::Solarized,
+            Theme::Solarized => Theme::HighContrast,
+            Theme::HighContrast => Theme::Normal,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Theme::Normal => "normal",
+            Theme::Dark => "dark",
+            Theme::Sepia => "sepia",
+            Theme::Solarized => "solarized",
+            Theme::HighContrast => "high-contrast",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        [
+            Theme::Normal,
+            Theme::Dark,
+            Theme::Sepia,
+            Theme::Solarized,
+            Theme::HighContrast,
+        ]
+        .into_iter()
+        .find(|t| t.name() == name)
+    }
+
+    // paper and ink as 0xRRGGBB
+    fn colors(self) -> (u32, u32) {
+        match self {
+            Theme::Normal => (0xffffff, 0x000000),
+            Theme::Dark => (0x1c1c1c, 0xd4d4d4),
+            Theme::Sepia => (0xf4ecd8, 0x5b4636),
+            // solarized dark: base03 and base0
+            Theme::Solarized => (0x002b36, 0x839496),
+            Theme::HighContrast => (0x000000, 0xffffff),
+        }
+    }
+
+    pub fn uniform(self, vision: Vision) -> RecolorUniform {
+        let (paper, ink) = self.colors();
+        RecolorUniform {
+            paper: linear(paper),
+            ink: linear(ink),
+            vision: vision as u32,
+            _pad: [0; 3],
+        }
+    }
+}
+
+impl Vision {
+    pub fn next(self) -> Self {
+        match self {
+            Vision::Normal => Vision::Protanopia,
+            Vision::Protanopia => Vision::Deuteranopia,
+            Vision::Deuteranopia => Vision::Tritanopia,
+            Vision::Tritanopia => Vision::Daltonize,
+            Vision::Daltonize => Vision::Grayscale,
+            Vision::Grayscale => Vision::Normal,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Vision::Normal => "normal",
+            Vision::Protanopia => "protanopia",
+            Vision::Deuteranopia => "deuteranopia",
+            Vision::Tritanopia => "tritanopia",
+            Vision::Daltonize => "daltonize",
+            Vision::Grayscale => "grayscale",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        [
+            Vision::Normal,
+            Vision::Protanopia,
+            Vision::Deuteranopia,
+            Vision::Tritanopia,
+            Vision::Daltonize,
+            Vision::Grayscale,
+        ]
+        .into_iter()
+        .find(|v| v.name() == name)
+    }
+}
+
+// an srgb 0xRRGGBB as linear rgba
+fn linear(rgb: u32) -> [f32; 4] {
+    let channel = |shift: u32| {
+        let c = ((rgb >> shift) & 0xff) as f32 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    [channel(16), channel(8), channel(0), 1.0]
+}
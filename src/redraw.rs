@@ -0,0 +1,121 @@
+use std::time::Instant;
+
+// whether the window needs drawing. anything that changes what is on
+// screen asks for a frame, and things that will change later (a zoom
+// settling, an egui animation) ask for one at a time. the event loop
+// sleeps in between instead of drawing the same page over and over
+#[derive(Debug, Default)]
+pub struct Redraw {
+    dirty: bool,
+    // the earliest frame asked for in the future
+    wake: Option<Instant>,
+    // frames drawn so far
+    frames: u64,
+}
+
+impl Redraw {
+    // something changed; draw as soon as the event queue is empty
+    pub fn request(&mut self) {
+        self.dirty = true;
+    }
+
+    // draw at `at` even if nothing else changes by then
+    pub fn request_at(&mut self, at: Instant) {
+        self.wake = Some(self.wake.map_or(at, |wake| wake.min(at)));
+    }
+
+    // whether a frame is due at `now`
+    pub fn due(&mut self, now: Instant) -> bool {
+        if self.wake.is_some_and(|wake| wake <= now) {
+            self.wake = None;
+            self.dirty = true;
+        }
+        self.dirty
+    }
+
+    // when to wake up for the next frame if none is due now
+    pub fn wake(&self) -> Option<Instant> {
+        self.wake
+    }
+
+    // a frame is starting. what is asked for while it is drawn goes
+    // into the next one
+    pub fn start(&mut self) {
+        self.dirty = false;
+    }
+
+    // a frame made it to the screen
+    pub fn finish(&mut self) {
+        self.frames += 1;
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // what the event loop does once its queue is empty
+    fn idle(redraw: &mut Redraw, now: Instant) {
+        if redraw.due(now) {
+            redraw.start();
+            redraw.finish();
+        }
+    }
+
+    #[test]
+    fn static_page_draws_nothing() {
+        let mut redraw = Redraw::default();
+        let now = Instant::now();
+        redraw.request();
+        for _ in 0..10 {
+            idle(&mut redraw, now);
+        }
+        assert_eq!(redraw.frames(), 1);
+    }
+
+    #[test]
+    fn each_change_draws_once() {
+        let mut redraw = Redraw::default();
+        let now = Instant::now();
+        for _ in 0..3 {
+            redraw.request();
+            redraw.request();
+            idle(&mut redraw, now);
+            idle(&mut redraw, now);
+        }
+        assert_eq!(redraw.frames(), 3);
+    }
+
+    #[test]
+    fn waits_for_a_later_frame() {
+        let mut redraw = Redraw::default();
+        let now = Instant::now();
+        let later = now + Duration::from_millis(300);
+        redraw.request_at(later + Duration::from_millis(100));
+        redraw.request_at(later);
+        assert_eq!(redraw.wake(), Some(later));
+        idle(&mut redraw, now);
+        assert_eq!(redraw.frames(), 0);
+        idle(&mut redraw, later);
+        assert_eq!(redraw.frames(), 1);
+        assert_eq!(redraw.wake(), None);
+    }
+
+    #[test]
+    fn requests_while_drawing_go_to_the_next_frame() {
+        let mut redraw = Redraw::default();
+        let now = Instant::now();
+        redraw.request();
+        assert!(redraw.due(now));
+        redraw.start();
+        redraw.request();
+        redraw.finish();
+        idle(&mut redraw, now);
+        assert_eq!(redraw.frames(), 2);
+    }
+}
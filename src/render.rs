@@ -0,0 +1,357 @@
+use std::borrow::Cow;
+
+use anyhow::*;
+use wgpu::util::DeviceExt;
+
+use crate::camera::Camera;
+use crate::page::{self, PageSlot, Vertex, SQUARE_INDICES};
+use crate::recolor::{Theme, Vision};
+use crate::tiles::Atlas;
+use crate::wgsl;
+
+// the page pipeline from shader.wgsl, apart from what it draws into: the
+// window's surface or an Offscreen texture
+pub struct Renderer {
+    render_pipeline: wgpu::RenderPipeline,
+    // kept to build the pipeline again from another shader
+    pipeline_layout: wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    index_buffer: wgpu::Buffer,
+    num_indices: u32,
+    pub texture_bind_group_layout: wgpu::BindGroupLayout,
+    camera_buffer: wgpu::Buffer,
+    camera_bind_group: wgpu::BindGroup,
+    recolor_buffer: wgpu::Buffer,
+    recolor_bind_group: wgpu::BindGroup,
+}
+
+impl Renderer {
+    // a renderer for targets of `format`
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        let texture_bind_group_layout = page::bind_group_layout(device);
+
+        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Camera Buffer"),
+            contents: bytemuck::cast_slice(&[Camera::new().uniform()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let camera_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some("camera_bind_group_layout"),
+            });
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &camera_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            }],
+            label: Some("camera_bind_group"),
+        });
+
+        let recolor_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Recolor Buffer"),
+            contents: bytemuck::cast_slice(&[Theme::Normal.uniform(Vision::Normal)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let recolor_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some("recolor_bind_group_layout"),
+            });
+        let recolor_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &recolor_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: recolor_buffer.as_entire_binding(),
+            }],
+            label: Some("recolor_bind_group"),
+        });
+
+        let shader = device.create_shader_module(wgpu::include_wgsl!("./shader.wgsl"));
+
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Render Pipeline Layout"),
+                bind_group_layouts: &[
+                    &texture_bind_group_layout,
+                    &camera_bind_group_layout,
+                    &recolor_bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+
+        let render_pipeline = pipeline(device, &render_pipeline_layout, &shader, format);
+
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Index Buffer"),
+            contents: bytemuck::cast_slice(SQUARE_INDICES),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        let num_indices = SQUARE_INDICES.len() as u32;
+
+        Self {
+            render_pipeline,
+            pipeline_layout: render_pipeline_layout,
+            format,
+            index_buffer,
+            num_indices,
+            texture_bind_group_layout,
+            camera_buffer,
+            camera_bind_group,
+            recolor_buffer,
+            recolor_bind_group,
+        }
+    }
+
+    // draw with `source` in place of shader.wgsl, which has to keep its
+    // entry points and bindings. on an error the pipeline stays as it was
+    pub fn set_shader(&mut self, device: &wgpu::Device, name: &str, source: &str) -> Result<()> {
+        let module = wgsl::validate(name, source)?;
+        for (entry, stage) in [
+            ("vs_main", naga::ShaderStage::Vertex),
+            ("fs_main", naga::ShaderStage::Fragment),
+        ] {
+            if !wgsl::has_entry_point(&module, entry, stage) {
+                bail!("{}: no {:?} entry point {}", name, stage, entry);
+            }
+        }
+        self.render_pipeline = wgsl::checked(device, || {
+            let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some(name),
+                source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(source)),
+            });
+            pipeline(device, &self.pipeline_layout, &shader, self.format)
+        })
+        .with_context(|| name.to_string())?;
+        Ok(())
+    }
+
+    pub fn set_camera(&self, queue: &wgpu::Queue, camera: &Camera) {
+        queue.write_buffer(
+            &self.camera_buffer,
+            0,
+            bytemuck::cast_slice(&[camera.uniform()]),
+        );
+    }
+
+    // draw pages in `theme`'s colors, then through `vision`'s filter,
+    // from the next frame on
+    pub fn set_theme(&self, queue: &wgpu::Queue, theme: Theme, vision: Vision) {
+        queue.write_buffer(
+            &self.recolor_buffer,
+            0,
+            bytemuck::cast_slice(&[theme.uniform(vision)]),
+        );
+    }
+
+    // clear `view` to `background`, then draw `slots` in order and the
+    // atlas's tiles over them
+    pub fn draw<'a>(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        background: wgpu::Color,
+        slots: impl IntoIterator<Item = &'a PageSlot>,
+        atlas: &Atlas,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(background),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        let idxbuf = &self.index_buffer;
+        let numidx = self.num_indices;
+
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
+        render_pass.set_bind_group(2, &self.recolor_bind_group, &[]);
+        render_pass.set_index_buffer(idxbuf.slice(..), wgpu::IndexFormat::Uint16);
+        for slot in slots {
+            render_pass.set_bind_group(0, &slot.bind_group, &[]);
+            render_pass.set_vertex_buffer(0, slot.vertex_buffer.slice(..));
+            render_pass.draw_indexed(0..numidx, 0, 0..1);
+        }
+        atlas.draw(&mut render_pass);
+    }
+}
+
+// the page pipeline around `shader`'s vs_main and fs_main
+fn pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Render Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[Vertex::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            polygon_mode: wgpu::PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
+
+// a texture to render into instead of a window, for tests and export
+pub struct Offscreen {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Offscreen {
+    // `format` is the one the renderer was made for, rgba or bgra. srgb
+    // ones read back the pixels that would be on screen
+    pub fn new(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            label: Some("offscreen"),
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        Self {
+            texture,
+            view,
+            width,
+            height,
+        }
+    }
+
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    // copy what was rendered back to the cpu, waiting for the gpu to finish
+    pub fn read(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<image::RgbaImage> {
+        // buffer rows have to be padded to the copy alignment
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let row = (4 * self.width).div_ceil(align) * align;
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Offscreen Readback"),
+            size: (row * self.height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Readback Encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            self.texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(row),
+                    rows_per_image: Some(self.height),
+                },
+            },
+            self.texture.size(),
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let slice = buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = tx.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        rx.recv()?.context("could not map the readback buffer")?;
+
+        let mapped = slice.get_mapped_range();
+        let mut pixels = Vec::with_capacity((4 * self.width * self.height) as usize);
+        for line in mapped.chunks(row as usize) {
+            pixels.extend_from_slice(&line[..(4 * self.width) as usize]);
+        }
+        drop(mapped);
+        buffer.unmap();
+        if matches!(
+            self.texture.format(),
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+        ) {
+            for px in pixels.chunks_exact_mut(4) {
+                px.swap(0, 2);
+            }
+        }
+        image::RgbaImage::from_raw(self.width, self.height, pixels)
+            .context("readback has the wrong size")
+    }
+
+    pub fn save_png(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: &std::path::Path,
+    ) -> Result<()> {
+        self.read(device, queue)?
+            .save_with_format(path, image::ImageFormat::Png)
+            .with_context(|| format!("could not write {}", path.display()))
+    }
+}
@@ -6,6 +6,14 @@ struct VertexInput {
 
 }
 
+struct CameraUniform {
+    scale: vec2<f32>,
+    offset: vec2<f32>,
+}
+
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
@@ -19,7 +27,7 @@ fn vs_main(
     var out: VertexOutput;
     out.bg_color = model.bg_color;
     out.tex_coords = model.tex_coords;
-    out.clip_position = vec4<f32>(model.position, 1.0);
+    out.clip_position = vec4<f32>(model.position.xy * camera.scale + camera.offset, model.position.z, 1.0);
     return out;
 }
 
@@ -27,15 +35,75 @@ fn vs_main(
 var t_diffuse: texture_2d<f32>;
 @group(0)@binding(1)
 var s_diffuse: sampler;
+// 1 where the page shows an image, which keeps its own colors
+@group(0) @binding(2)
+var t_mask: texture_2d<f32>;
+
+// the colors the page's paper and ink are drawn in, see recolor.rs
+struct Recolor {
+    paper: vec4<f32>,
+    ink: vec4<f32>,
+    // a recolor::Vision, in its declaration order
+    vision: u32,
+}
+
+@group(2) @binding(0)
+var<uniform> recolor: Recolor;
+
+// dichromat simulations for linear rgb, from Machado, Oliveira and
+// Fernandes (2009) at full severity. each vector is a row of the
+// matrix, so they multiply with the color on the left
+const PROTANOPIA = mat3x3<f32>(
+    vec3<f32>(0.152286, 1.052583, -0.204868),
+    vec3<f32>(0.114503, 0.786281, 0.099216),
+    vec3<f32>(-0.003882, -0.048116, 1.051998),
+);
+const DEUTERANOPIA = mat3x3<f32>(
+    vec3<f32>(0.367322, 0.860646, -0.227968),
+    vec3<f32>(0.280085, 0.672501, 0.047413),
+    vec3<f32>(-0.011820, 0.042940, 0.968881),
+);
+const TRITANOPIA = mat3x3<f32>(
+    vec3<f32>(1.255528, -0.076749, -0.178779),
+    vec3<f32>(-0.078411, 0.930809, 0.147602),
+    vec3<f32>(0.004733, 0.691367, 0.303900),
+);
+
+fn vision(color: vec3<f32>) -> vec3<f32> {
+    switch recolor.vision {
+        case 1u: {
+            return clamp(color * PROTANOPIA, vec3<f32>(0.0), vec3<f32>(1.0));
+        }
+        case 2u: {
+            return clamp(color * DEUTERANOPIA, vec3<f32>(0.0), vec3<f32>(1.0));
+        }
+        case 3u: {
+            return clamp(color * TRITANOPIA, vec3<f32>(0.0), vec3<f32>(1.0));
+        }
+        case 4u: {
+            // what a deuteranope loses, moved into the channels they
+            // still tell apart (Fidaner, Lin and Ozguven)
+            let lost = color - color * DEUTERANOPIA;
+            let shift = vec3<f32>(0.0, 0.7 * lost.r + lost.g, 0.7 * lost.r + lost.b);
+            return clamp(color + shift, vec3<f32>(0.0), vec3<f32>(1.0));
+        }
+        case 5u: {
+            return vec3<f32>(dot(color, vec3<f32>(0.2126, 0.7152, 0.0722)));
+        }
+        default: {
+            return color;
+        }
+    }
+}
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    var tex = textureSample(t_diffuse, s_diffuse, in.tex_coords);
-    var col = vec4<f32>(1.0, 1.0, 1.0, 1.0);
-    var p = tex[3];
-
-    return vec4<f32>(p*tex[0] + (1.0 -p)*col[0], 
-        p*tex[1] + (1.0 - p)*col[1], 
-        p*tex[2] + (1.0 - p)*col[2],
-        1.0);
-}
\ No newline at end of file
+    let tex = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    // rasters are premultiplied, with the paper left transparent
+    let page = tex.rgb + (1.0 - tex.a) * in.bg_color;
+    // black goes to the ink, white to the paper and the rest in between
+    let themed = mix(recolor.ink.rgb, recolor.paper.rgb, page);
+    let image = textureSample(t_mask, s_diffuse, in.tex_coords).r;
+    let color = mix(themed, page, image);
+    return vec4<f32>(vision(color), 1.0);
+}
@@ -1,5 +1,10 @@
-use image::GenericImageView;
 use anyhow::*;
+use wgpu::util::DeviceExt;
+
+// a free texture is reused for rasters up to this many pixels smaller.
+// mipmapped ones only for rasters of their exact size, since the lower
+// levels would average the stale texels past the raster's edge into it
+const SLACK: u32 = 64;
 
 pub struct Texture {
     pub texture: wgpu::Texture,
@@ -8,17 +13,20 @@ pub struct Texture {
 }
 
 impl Texture {
+    // with `mips` the texture gets a full mip chain generated on the gpu
+    // and trilinear, anisotropic sampling for when it is shown smaller
     pub fn from_bytes(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        bytes: &[u8],
-        width: u32,
-        height: u32, 
-        label: &str
+        converter: &Converter,
+        pixels: &Pixels,
+        label: &str,
+        mips: Option<&Mipmapper>
     ) -> Result<Self> {
+        let (width, height) = (pixels.width, pixels.height);
         let texture_size = wgpu::Extent3d {
-            width: width,
-            height: height,
+            width,
+            height,
             depth_or_array_layers: 1,
         };
 
@@ -26,103 +34,692 @@ impl Texture {
             // All textures are stored as 3D, we represent our 2D texture
             // by setting depth to 1.
             size: texture_size,
-            mip_level_count: 1, 
+            mip_level_count: if mips.is_some() { mip_levels(width, height) } else { 1 },
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             // Most images are stored using sRGB so we need to reflect that here.
             format: wgpu::TextureFormat::Rgba8UnormSrgb,
             // TEXTURE_BINDING tells wgpu that we want to use this texture in shaders
             // COPY_DST means that we want to copy data to this texture
+            // RENDER_ATTACHMENT lets the mip levels be drawn into
+            usage: if mips.is_some() {
+                wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_DST
+                    | wgpu::TextureUsages::RENDER_ATTACHMENT
+            } else {
+                wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST
+            },
+            label: Some(label),
+            view_formats: &[],
+        });
+
+        converter.write(device, queue, &texture, pixels, 0, 0);
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = match mips {
+            Some(mips) => {
+                mips.generate(device, queue, &texture, width, height);
+                device.create_sampler(&wgpu::SamplerDescriptor {
+                    address_mode_u: wgpu::AddressMode::ClampToEdge,
+                    address_mode_v: wgpu::AddressMode::ClampToEdge,
+                    address_mode_w: wgpu::AddressMode::ClampToEdge,
+                    mag_filter: wgpu::FilterMode::Linear,
+                    min_filter: wgpu::FilterMode::Linear,
+                    mipmap_filter: wgpu::FilterMode::Linear,
+                    // ignored where the gpu can't filter anisotropically
+                    anisotropy_clamp: 16,
+                    ..Default::default()
+                })
+            }
+            None => device.create_sampler(&wgpu::SamplerDescriptor {
+                address_mode_u: wgpu::AddressMode::ClampToEdge,
+                address_mode_v: wgpu::AddressMode::ClampToEdge,
+                address_mode_w: wgpu::AddressMode::ClampToEdge,
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Nearest,
+                mipmap_filter: wgpu::FilterMode::Nearest,
+                ..Default::default()
+            }),
+        };
+        Ok(Self {texture, view, sampler})
+    }
+
+    // an uninitialized texture to fill piecewise with Converter::write
+    pub fn blank(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        label: &str
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
             label: Some(label),
             view_formats: &[],
         });
 
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        Self { texture, view, sampler }
+    }
+}
+
+// a byte per texel marking where a page shows images, sampled alongside
+// its texture. new masks are all zero, so a 1x1 one marks nothing
+pub struct Mask {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+}
+
+impl Mask {
+    pub fn new(device: &wgpu::Device, width: u32, height: u32, label: &str) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            label: Some(label),
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        Self { texture, view }
+    }
+
+    // write `width` by `height` tightly packed bytes with their top left at (x, y)
+    pub fn write(
+        &self,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+        width: u32,
+        height: u32,
+        x: u32,
+        y: u32,
+    ) {
         queue.write_texture(
             wgpu::ImageCopyTexture {
-                texture: &texture,
+                texture: &self.texture,
                 mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
+                origin: wgpu::Origin3d { x, y, z: 0 },
                 aspect: wgpu::TextureAspect::All,
             },
             bytes,
             wgpu::ImageDataLayout {
                 offset: 0,
-                bytes_per_row: Some(4 * width),
+                bytes_per_row: Some(width),
                 rows_per_image: Some(height),
             },
-            texture_size,
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
         );
+    }
 
-        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    // texture memory used, in bytes
+    pub fn bytes(&self) -> u64 {
+        self.texture.width() as u64 * self.texture.height() as u64
+    }
+}
+
+// textures set aside for reuse, so turning to a page the size of one
+// already seen writes into an existing texture instead of allocating
+pub struct Pool {
+    free: Vec<Texture>,
+    // how many free textures to hold on to
+    limit: usize,
+    // mipmaps the textures it hands out, when set
+    mips: Option<Mipmapper>,
+}
+
+impl Pool {
+    pub fn new(limit: usize, mips: Option<Mipmapper>) -> Self {
+        Self { free: Vec::new(), limit, mips }
+    }
+
+    // a texture holding the `width` x `height` rgba `bytes` in its top
+    // left corner. a pooled texture without mips may be a little larger
+    // than that
+    pub fn upload(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        converter: &Converter,
+        pixels: &Pixels,
+        label: &str
+    ) -> Result<Texture> {
+        let (width, height) = (pixels.width, pixels.height);
+        let mips = self.mips.as_ref();
+        let slack = if mips.is_some() { 1 } else { SLACK };
+        let fits = |t: &Texture| {
+            let (tw, th) = (t.texture.width(), t.texture.height());
+            let mipmapped = t.texture.mip_level_count() > 1;
+            tw >= width && th >= height && tw - width < slack && th - height < slack
+                && mipmapped == mips.is_some()
+        };
+        match self.free.iter().position(fits) {
+            Some(i) => {
+                let texture = self.free.swap_remove(i);
+                converter.write(device, queue, &texture.texture, pixels, 0, 0);
+                if let Some(mips) = mips {
+                    mips.generate(device, queue, &texture.texture, width, height);
+                }
+                Ok(texture)
+            }
+            None => Texture::from_bytes(device, queue, converter, pixels, label, mips),
+        }
+    }
+
+    // hand back a texture that is no longer drawn
+    pub fn recycle(&mut self, texture: Texture) {
+        self.free.push(texture);
+        if self.free.len() > self.limit {
+            self.free.remove(0);
+        }
+    }
+}
+
+// levels down to 1x1
+fn mip_levels(width: u32, height: u32) -> u32 {
+    32 - width.max(height).max(1).leading_zeros()
+}
+
+// how mip levels are filtered down from the one above
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Downsample {
+    // average each 2x2 block
+    Box,
+    // a wider tent filter, softer but steadier on small text
+    Tent,
+}
+
+impl Downsample {
+    pub fn name(self) -> &'static str {
+        match self {
+            Downsample::Box => "box",
+            Downsample::Tent => "tent",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        [Downsample::Box, Downsample::Tent]
+            .into_iter()
+            .find(|d| d.name() == name)
+    }
+}
+
+// fills in mip chains by drawing each level from the one above it
+pub struct Mipmapper {
+    pipeline: wgpu::RenderPipeline,
+    layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+}
+
+impl Mipmapper {
+    pub fn new(device: &wgpu::Device, downsample: Downsample) -> Self {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("./mipmap.wgsl"));
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("mipmap_bind_group_layout"),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Mipmap Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Mipmap Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: match downsample {
+                    Downsample::Box => "fs_box",
+                    Downsample::Tent => "fs_tent",
+                },
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::ClampToEdge,
             address_mode_v: wgpu::AddressMode::ClampToEdge,
             address_mode_w: wgpu::AddressMode::ClampToEdge,
             mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Linear,
             ..Default::default()
         });
-        return Ok(Self {texture, view, sampler})
+        Self { pipeline, layout, sampler }
     }
 
-    pub fn from_image(
+    // regenerate every level below the first from the `width` x `height`
+    // corner of level 0 that holds the raster
+    pub fn generate(
+        &self,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        img: &image::DynamicImage,
-        label: Option<&str>
-    ) -> Result<Self> {
-        let rgba = img.to_rgba8();
-        let dimensions = img.dimensions();
+        texture: &wgpu::Texture,
+        width: u32,
+        height: u32
+    ) {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Mipmap Encoder"),
+        });
+        let level_view = |level: u32| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                base_mip_level: level,
+                mip_level_count: Some(1),
+                ..Default::default()
+            })
+        };
+        for level in 1..texture.mip_level_count() {
+            let source = level_view(level - 1);
+            let target = level_view(level);
+            let source_size = [
+                (texture.width() >> (level - 1)).max(1) as f32,
+                (texture.height() >> (level - 1)).max(1) as f32,
+                0.0,
+                0.0,
+            ];
+            let source_size = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Mipmap Source Size"),
+                contents: bytemuck::cast_slice(&source_size),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &self.layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&source),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: source_size.as_entire_binding(),
+                    },
+                ],
+                label: Some("mipmap_bind_group"),
+            });
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Mipmap Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            // only the part holding the raster, so a pooled texture's
+            // leftover edge never mixes in
+            let size = |used: u32, full: u32| {
+                let rounded_up = (used + (1 << level) - 1) >> level;
+                rounded_up.min(full >> level).max(1)
+            };
+            let w = size(width, texture.width());
+            let h = size(height, texture.height());
+            pass.set_viewport(0.0, 0.0, w as f32, h as f32, 0.0, 1.0);
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+}
+
+// the colorants of a pixmap, in sample order
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Colors {
+    Gray,
+    Rgb,
+    Bgr,
+    Cmyk,
+}
+
+impl Colors {
+    fn count(self) -> u32 {
+        match self {
+            Colors::Gray => 1,
+            Colors::Rgb | Colors::Bgr => 3,
+            Colors::Cmyk => 4,
+        }
+    }
+}
+
+// how the samples of a pixmap are laid out
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Format {
+    pub colors: Colors,
+    // a premultiplied alpha sample follows the colorants
+    pub alpha: bool,
+}
+
+impl Format {
+    pub const RGBA: Format = Format {
+        colors: Colors::Rgb,
+        alpha: true,
+    };
+
+    // what the samples of `pixmap` hold
+    pub fn of(pixmap: &mupdf::Pixmap) -> Result<Format> {
+        let alpha = pixmap.alpha();
+        let colorants = pixmap.n() as u32 - alpha as u32;
+        let colors = match pixmap.color_space() {
+            Some(cs) if cs.is_gray() => Colors::Gray,
+            Some(cs) if cs.name() == "DeviceBGR" => Colors::Bgr,
+            Some(cs) if cs.is_rgb() => Colors::Rgb,
+            Some(cs) if cs.is_cmyk() => Colors::Cmyk,
+            // no or some other colorspace, go by the number of colorants
+            _ => match colorants {
+                1 => Colors::Gray,
+                3 => Colors::Rgb,
+                4 => Colors::Cmyk,
+                n => bail!("can't display a pixmap with {} colorants", n),
+            },
+        };
+        Ok(Format { colors, alpha })
+    }
+
+    // bytes per pixel
+    pub fn components(self) -> u32 {
+        self.colors.count() + self.alpha as u32
+    }
+}
+
+// pixmap samples on their way to a texture
+pub struct Pixels<'a> {
+    pub bytes: &'a [u8],
+    pub width: u32,
+    pub height: u32,
+    // bytes from one row to the next
+    pub stride: u32,
+    pub format: Format,
+}
+
+// the parameter block of convert.wgsl
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ConvertParams {
+    width: u32,
+    height: u32,
+    stride: u32,
+    components: u32,
+    colors: u32,
+    alpha: u32,
+    out_row: u32,
+    _pad: u32,
+}
+
+// the one way pixels get into textures. rgba is written as is, anything
+// else is expanded to rgba by a compute shader first
+pub struct Converter {
+    pipeline: wgpu::ComputePipeline,
+    layout: wgpu::BindGroupLayout,
+}
+
+impl Converter {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("./convert.wgsl"));
+        let storage = |binding: u32, read_only: bool| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage(1, true),
+                storage(2, false),
+            ],
+            label: Some("convert_bind_group_layout"),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Convert Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Convert Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "main",
+        });
+        Self { pipeline, layout }
+    }
 
+    // write `pixels` into mip level 0 of `texture` with its top left at (x, y)
+    pub fn write(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture: &wgpu::Texture,
+        pixels: &Pixels,
+        x: u32,
+        y: u32
+    ) {
+        let target = wgpu::ImageCopyTexture {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d { x, y, z: 0 },
+            aspect: wgpu::TextureAspect::All,
+        };
         let size = wgpu::Extent3d {
-            width: dimensions.0,
-            height: dimensions.1,
+            width: pixels.width,
+            height: pixels.height,
             depth_or_array_layers: 1,
         };
-        let texture = device.create_texture(
-            &wgpu::TextureDescriptor {
-                label,
+        // buffer to texture copies need rows padded to the copy alignment
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let out_row = (4 * pixels.width).div_ceil(align) * align;
+        // pixmaps bigger than a storage binding may be are converted here
+        let limit = device.limits().max_storage_buffer_binding_size as u64;
+        let in_bytes = pixels.bytes.len().div_ceil(4) as u64 * 4;
+        let out_bytes = out_row as u64 * pixels.height as u64;
+        if pixels.format == Format::RGBA || in_bytes > limit || out_bytes > limit {
+            let converted;
+            let (bytes, stride) = if pixels.format == Format::RGBA {
+                (pixels.bytes, pixels.stride)
+            } else {
+                converted = rgba(pixels);
+                (&converted[..], 4 * pixels.width)
+            };
+            // queue writes take any row stride
+            queue.write_texture(
+                target,
+                bytes,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(stride),
+                    rows_per_image: Some(pixels.height),
+                },
                 size,
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba8UnormSrgb,
-                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-                view_formats: &[],
-            }
-        );
+            );
+            return;
+        }
 
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                aspect: wgpu::TextureAspect::All,
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
+        let params = ConvertParams {
+            width: pixels.width,
+            height: pixels.height,
+            stride: pixels.stride,
+            components: pixels.format.components(),
+            colors: match pixels.format.colors {
+                Colors::Gray => 0,
+                Colors::Rgb => 1,
+                Colors::Bgr => 2,
+                Colors::Cmyk => 3,
             },
-            &rgba,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * dimensions.0),
-                rows_per_image: Some(dimensions.1),
+            alpha: pixels.format.alpha as u32,
+            out_row: out_row / 4,
+            _pad: 0,
+        };
+        let params = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Convert Params"),
+            contents: bytemuck::bytes_of(&params),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        // storage buffers are read a word at a time
+        let mut samples = pixels.bytes.to_vec();
+        samples.resize(samples.len().div_ceil(4) * 4, 0);
+        let samples = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Convert Samples"),
+            contents: &samples,
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let output = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Convert Output"),
+            size: (out_row * pixels.height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: samples.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: output.as_entire_binding(),
+                },
+            ],
+            label: Some("convert_bind_group"),
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Convert Encoder"),
+        });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Convert Pass"),
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(pixels.width.div_ceil(8), pixels.height.div_ceil(8), 1);
+        }
+        encoder.copy_buffer_to_texture(
+            wgpu::ImageCopyBuffer {
+                buffer: &output,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(out_row),
+                    rows_per_image: Some(pixels.height),
+                },
             },
+            target,
             size,
         );
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+}
 
-        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(
-            &wgpu::SamplerDescriptor {
-                address_mode_u: wgpu::AddressMode::ClampToEdge,
-                address_mode_v: wgpu::AddressMode::ClampToEdge,
-                address_mode_w: wgpu::AddressMode::ClampToEdge,
-                mag_filter: wgpu::FilterMode::Linear,
-                min_filter: wgpu::FilterMode::Nearest,
-                mipmap_filter: wgpu::FilterMode::Nearest,
-                ..Default::default()
-            }
-        );
-        
-        Ok(Self { texture, view, sampler })
+// what convert.wgsl does, on the cpu
+fn rgba(pixels: &Pixels) -> Vec<u8> {
+    let n = pixels.format.components() as usize;
+    let width = pixels.width as usize;
+    let mut out = Vec::with_capacity(4 * width * pixels.height as usize);
+    let rows = pixels.bytes.chunks(pixels.stride as usize).take(pixels.height as usize);
+    for row in rows {
+        for px in row[..n * width].chunks_exact(n) {
+            let a = if pixels.format.alpha { px[n - 1] } else { 255 };
+            let rgb = match pixels.format.colors {
+                Colors::Gray => [px[0]; 3],
+                Colors::Rgb => [px[0], px[1], px[2]],
+                Colors::Bgr => [px[2], px[1], px[0]],
+                Colors::Cmyk => {
+                    // naive cmyk, done unpremultiplied and premultiplied again
+                    let alpha = a as f32 / 255.0;
+                    let c = |i: usize| (px[i] as f32 / 255.0 / alpha.max(1.0 / 255.0)).min(1.0);
+                    let k = 1.0 - c(3);
+                    [0, 1, 2].map(|i| ((1.0 - c(i)) * k * alpha * 255.0).round() as u8)
+                }
+            };
+            out.extend_from_slice(&[rgb[0], rgb[1], rgb[2], a]);
+        }
     }
+    out
 }
@@ -0,0 +1,296 @@
+use crate::layout::Quad;
+use crate::page::{self, Vertex};
+use crate::raster::{Options, Raster};
+use crate::texture;
+
+// tiles are square, this many pixels on a side
+pub const TILE: u32 = 256;
+// the atlas is at most this many pixels on a side
+const ATLAS_SIZE: u32 = 4096;
+// the deepest level, 2^16 times the raster scale of level 0
+const MAX_LEVEL: u32 = 16;
+
+// one square of a page's quadtree. level 0 is the whole page in a single
+// tile, and every level below doubles the raster scale and splits each
+// tile in four
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tile {
+    pub page: i32,
+    pub level: u32,
+    pub x: u32,
+    pub y: u32,
+    pub options: Options,
+}
+
+impl Tile {
+    // the raster scale of this tile's level for a page of `size` points
+    pub fn scale(&self, size: (f32, f32)) -> f32 {
+        level_scale(size, self.level)
+    }
+
+    pub fn parent(&self) -> Option<Tile> {
+        let level = self.level.checked_sub(1)?;
+        Some(Tile {
+            level,
+            x: self.x / 2,
+            y: self.y / 2,
+            ..*self
+        })
+    }
+
+    // the pixels this tile covers in the page rasterized at its scale,
+    // as x0, y0, x1, y1
+    pub fn pixels(&self, size: (f32, f32)) -> [u32; 4] {
+        let (w, h) = raster_size(size, self.level);
+        [
+            self.x * TILE,
+            self.y * TILE,
+            ((self.x + 1) * TILE).min(w.ceil() as u32),
+            ((self.y + 1) * TILE).min(h.ceil() as u32),
+        ]
+    }
+
+    // the part of the page this tile covers, from 0 to 1 down from the top left
+    fn area(&self, size: (f32, f32)) -> Quad {
+        let (w, h) = raster_size(size, self.level);
+        let [x0, y0, x1, y1] = self.pixels(size);
+        Quad {
+            left: x0 as f32 / w,
+            top: y0 as f32 / h,
+            right: (x1 as f32 / w).min(1.0),
+            bottom: (y1 as f32 / h).min(1.0),
+        }
+    }
+}
+
+fn level_scale(size: (f32, f32), level: u32) -> f32 {
+    TILE as f32 / size.0.max(size.1).max(1.0) * (1u32 << level) as f32
+}
+
+fn raster_size(size: (f32, f32), level: u32) -> (f32, f32) {
+    let scale = level_scale(size, level);
+    (size.0 * scale, size.1 * scale)
+}
+
+// the first level whose raster scale reaches `scale`
+pub fn level_for(size: (f32, f32), scale: f32) -> u32 {
+    (0..MAX_LEVEL)
+        .find(|&level| level_scale(size, level) >= scale)
+        .unwrap_or(MAX_LEVEL)
+}
+
+// the tiles of `page` at `level` that overlap `area`, which is given
+// like Tile::area
+pub fn covering(
+    page: i32,
+    size: (f32, f32),
+    level: u32,
+    options: Options,
+    area: Quad,
+) -> Vec<Tile> {
+    let (w, h) = raster_size(size, level);
+    let t = TILE as f32;
+    let (nx, ny) = ((w / t).ceil() as u32, (h / t).ceil() as u32);
+    let xs = ((area.left * w / t) as u32).min(nx)..((area.right * w / t).ceil() as u32).min(nx);
+    let ys = ((area.top * h / t) as u32).min(ny)..((area.bottom * h / t).ceil() as u32).min(ny);
+    ys.flat_map(|y| {
+        xs.clone().map(move |x| Tile {
+            page,
+            level,
+            x,
+            y,
+            options,
+        })
+    })
+    .collect()
+}
+
+// a tile that should be drawn, with the size of its page in points and
+// where the page sits in clip space
+#[derive(Clone, Copy, Debug)]
+pub struct Placed {
+    pub tile: Tile,
+    pub size: (f32, f32),
+    pub quad: Quad,
+}
+
+struct Cell {
+    tile: Tile,
+    // size of the raster, up to TILE on a side
+    width: u32,
+    height: u32,
+    // the frame the cell was last drawn in
+    used: u64,
+}
+
+// one texture holding many tiles in a grid of TILE sized cells, reused
+// least recently drawn first. all its tiles go out in a single draw
+pub struct Atlas {
+    texture: texture::Texture,
+    // where the tiles show images, laid out like `texture`
+    mask: texture::Mask,
+    bind_group: wgpu::BindGroup,
+    cells: Vec<Option<Cell>>,
+    per_row: u32,
+    side: u32,
+    frame: u64,
+    vertex_buffer: wgpu::Buffer,
+    vertex_count: u32,
+}
+
+impl Atlas {
+    pub fn new(device: &wgpu::Device, layout: &wgpu::BindGroupLayout) -> Self {
+        let limit = device.limits().max_texture_dimension_2d;
+        let per_row = ATLAS_SIZE.min(limit) / TILE;
+        let side = per_row * TILE;
+        let texture = texture::Texture::blank(device, side, side, "tile_atlas");
+        let mask = texture::Mask::new(device, side, side, "tile_atlas_mask");
+        let bind_group = page::bind_group(device, layout, &texture, &mask);
+        let vertex_buffer = vertex_buffer(device, 0);
+        Self {
+            texture,
+            mask,
+            bind_group,
+            cells: (0..per_row * per_row).map(|_| None).collect(),
+            per_row,
+            side,
+            frame: 0,
+            vertex_buffer,
+            vertex_count: 0,
+        }
+    }
+
+    // how many tiles fit at once
+    pub fn capacity(&self) -> usize {
+        self.cells.len()
+    }
+
+    fn find(&self, tile: &Tile) -> Option<usize> {
+        self.cells
+            .iter()
+            .position(|c| c.as_ref().is_some_and(|c| c.tile == *tile))
+    }
+
+    pub fn contains(&self, tile: &Tile) -> bool {
+        self.find(tile).is_some()
+    }
+
+    // upload `tile` into a free cell, or the one drawn longest ago
+    pub fn insert(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        converter: &texture::Converter,
+        tile: Tile,
+        raster: &Raster,
+    ) {
+        let i = self.find(&tile).unwrap_or_else(|| {
+            (0..self.cells.len())
+                .min_by_key(|&i| self.cells[i].as_ref().map_or(0, |c| c.used + 1))
+                .unwrap_or(0)
+        });
+        let (x, y) = self.origin(i);
+        let (width, height) = (raster.width, raster.height);
+        converter.write(device, queue, &self.texture.texture, &raster.pixels(), x, y);
+        // the cell's last tile may have had images where this one has none
+        let blank;
+        let mask = match &raster.mask {
+            Some(mask) => mask,
+            None => {
+                blank = vec![0; (width * height) as usize];
+                &blank
+            }
+        };
+        self.mask.write(queue, mask, width, height, x, y);
+        self.cells[i] = Some(Cell {
+            tile,
+            width,
+            height,
+            used: self.frame,
+        });
+    }
+
+    fn origin(&self, cell: usize) -> (u32, u32) {
+        let cell = cell as u32;
+        ((cell % self.per_row) * TILE, (cell / self.per_row) * TILE)
+    }
+
+    // build this frame's vertices. a tile that is not in yet is drawn
+    // from the nearest coarser tile that is, stretched over the same area
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, placed: &[Placed]) {
+        self.frame += 1;
+        let mut vertices: Vec<Vertex> = Vec::with_capacity(placed.len() * 6);
+        for p in placed {
+            let mut source = Some(p.tile);
+            let cell = loop {
+                let Some(tile) = source else {
+                    break None;
+                };
+                if let Some(i) = self.find(&tile) {
+                    break Some(i);
+                }
+                source = tile.parent();
+            };
+            let Some(i) = cell else {
+                continue;
+            };
+
+            let area = p.tile.area(p.size);
+            let (w, h) = (p.quad.right - p.quad.left, p.quad.top - p.quad.bottom);
+            let quad = Quad {
+                left: p.quad.left + area.left * w,
+                top: p.quad.top - area.top * h,
+                right: p.quad.left + area.right * w,
+                bottom: p.quad.top - area.bottom * h,
+            };
+
+            // where the tile's area falls inside the cell's raster, kept
+            // half a texel in so filtering doesn't bleed into the next cell
+            let (x, y) = self.origin(i);
+            let frame = self.frame;
+            let Some(c) = self.cells[i].as_mut() else {
+                continue;
+            };
+            c.used = frame;
+            let from = c.tile.area(p.size);
+            let (cw, ch) = (c.width as f32, c.height as f32);
+            let fx = |u: f32| ((u - from.left) / (from.right - from.left) * cw).clamp(0.5, cw - 0.5);
+            let fy = |v: f32| ((v - from.top) / (from.bottom - from.top) * ch).clamp(0.5, ch - 0.5);
+            let side = self.side as f32;
+            let uv = Quad {
+                left: (x as f32 + fx(area.left)) / side,
+                top: (y as f32 + fy(area.top)) / side,
+                right: (x as f32 + fx(area.right)) / side,
+                bottom: (y as f32 + fy(area.bottom)) / side,
+            };
+            vertices.extend(page::quad_triangles(quad, uv));
+        }
+
+        let bytes = std::mem::size_of_val(vertices.as_slice()) as u64;
+        if bytes > self.vertex_buffer.size() {
+            self.vertex_buffer = vertex_buffer(device, vertices.len());
+        }
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vertices));
+        self.vertex_count = vertices.len() as u32;
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if self.vertex_count == 0 {
+            return;
+        }
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(0..self.vertex_count, 0..1);
+    }
+}
+
+// room for at least `vertices` vertices, and never less than a few tiles
+fn vertex_buffer(device: &wgpu::Device, vertices: usize) -> wgpu::Buffer {
+    let vertices = vertices.max(64 * 6);
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Tile Vertex Buffer"),
+        size: (vertices * std::mem::size_of::<Vertex>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
@@ -0,0 +1,296 @@
+use std::time::Instant;
+
+use egui_winit::winit::{event::WindowEvent, window::Window};
+
+use pdf_rust::Command;
+
+use crate::labels::PageLabels;
+use crate::outline::Toc;
+
+// something the user asked for through the overlay
+pub enum Action {
+    Go(Command),
+    ExportOutline,
+    DismissErrors,
+}
+
+// what the overlay needs to know about the document each frame
+pub struct UiView<'a> {
+    pub page: i32,
+    pub page_count: i32,
+    pub labels: &'a PageLabels,
+    // the outline, when its side panel is open
+    pub toc: Option<&'a mut Toc>,
+    // a short message for the status line
+    pub notice: Option<&'a str>,
+    // the count typed so far ahead of a key, as in `12j`
+    pub pending: Option<i32>,
+    // shader and gpu errors, shown until dismissed
+    pub errors: Vec<&'a String>,
+}
+
+// egui overlay drawn on top of the page: status line, outline and go-to prompt
+pub struct Ui {
+    ctx: egui::Context,
+    winit: egui_winit::State,
+    renderer: egui_wgpu::Renderer,
+    // primitives and texture changes from the last `run`, consumed by `paint`
+    frame: Option<(Vec<egui::ClippedPrimitive>, egui::TexturesDelta)>,
+    prompt: Option<String>,
+    prompt_error: bool,
+    // when egui wants to be drawn again, for hover changes and animations
+    repaint: Option<Instant>,
+}
+
+impl Ui {
+    pub fn new(window: &Window, device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        let mut winit = egui_winit::State::new(window);
+        winit.set_pixels_per_point(window.scale_factor() as f32);
+        Self {
+            ctx: egui::Context::default(),
+            winit,
+            renderer: egui_wgpu::Renderer::new(device, format, None, 1),
+            frame: None,
+            prompt: None,
+            prompt_error: false,
+            repaint: None,
+        }
+    }
+
+    // feed a window event to egui; true if egui wants it for itself
+    pub fn on_event(&mut self, event: &WindowEvent) -> bool {
+        if let WindowEvent::ScaleFactorChanged { scale_factor, .. } = event {
+            self.winit.set_pixels_per_point(*scale_factor as f32);
+        }
+        let response = self.winit.on_event(&self.ctx, event);
+        if response.repaint {
+            self.repaint = Some(Instant::now());
+        }
+        // while the prompt is open every key belongs to it
+        let key_event = matches!(
+            event,
+            WindowEvent::KeyboardInput { .. } | WindowEvent::ReceivedCharacter(_)
+        );
+        response.consumed || (key_event && self.prompt.is_some())
+    }
+
+    pub fn repaint_at(&self) -> Option<Instant> {
+        self.repaint
+    }
+
+    pub fn open_prompt(&mut self) {
+        self.prompt = Some(String::new());
+        self.prompt_error = false;
+    }
+
+    // lay out this frame's widgets, returning what the user asked for
+    pub fn run(&mut self, window: &Window, mut view: UiView) -> Option<Action> {
+        let input = self.winit.take_egui_input(window);
+        let mut action = None;
+        let output = self.ctx.clone().run(input, |ctx| {
+            self.status_line(ctx, &view);
+            if let Some(toc) = view.toc.as_deref_mut() {
+                action = outline_panel(ctx, toc, view.page);
+            }
+            if let Some(cmd) = self.prompt_window(ctx, &view) {
+                action = Some(Action::Go(cmd));
+            }
+            if !view.errors.is_empty() && !error_window(ctx, &view.errors) {
+                action = Some(Action::DismissErrors);
+            }
+        });
+        self.winit
+            .handle_platform_output(window, &self.ctx, output.platform_output);
+        self.repaint = Instant::now().checked_add(output.repaint_after);
+        let primitives = self.ctx.tessellate(output.shapes);
+        self.frame = Some((primitives, output.textures_delta));
+        action
+    }
+
+    fn status_line(&self, ctx: &egui::Context, view: &UiView) {
+        let physical = format!("{} / {}", view.page + 1, view.page_count);
+        let text = match view.labels.label(view.page) {
+            Some(label) if label != (view.page + 1).to_string() => {
+                format!("{}  ({})", label, physical)
+            }
+            _ => physical,
+        };
+        egui::TopBottomPanel::bottom("status").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label(text);
+                if let Some(count) = view.pending {
+                    ui.strong(count.to_string());
+                }
+                if let Some(notice) = view.notice {
+                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                        ui.weak(notice);
+                    });
+                }
+            });
+        });
+    }
+
+    fn prompt_window(&mut self, ctx: &egui::Context, view: &UiView) -> Option<Command> {
+        let text = self.prompt.as_mut()?;
+        let mut submitted = false;
+        let mut cancelled = false;
+        egui::Window::new("Go to page")
+            .collapsible(false)
+            .resizable(false)
+            .title_bar(false)
+            .anchor(egui::Align2::CENTER_TOP, [0.0, 40.0])
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Go to:");
+                    let edit = ui.text_edit_singleline(text);
+                    edit.request_focus();
+                    submitted = ui.input(|i| i.key_pressed(egui::Key::Enter));
+                    cancelled = ui.input(|i| i.key_pressed(egui::Key::Escape));
+                });
+                if self.prompt_error {
+                    ui.colored_label(egui::Color32::LIGHT_RED, "no such page or label");
+                }
+                ui.weak("page label, page number, or #number for a physical page");
+            });
+        if cancelled {
+            self.prompt = None;
+            return None;
+        }
+        if !submitted {
+            return None;
+        }
+        match view.labels.resolve(text, view.page_count) {
+            Some(page) => {
+                self.prompt = None;
+                Some(Command::Goto(page))
+            }
+            None => {
+                self.prompt_error = true;
+                None
+            }
+        }
+    }
+
+    // upload and draw the last frame's widgets over the current contents of `view`
+    pub fn paint(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        size_in_pixels: [u32; 2],
+    ) {
+        let Some((primitives, textures)) = self.frame.take() else {
+            return;
+        };
+        let screen = egui_wgpu::renderer::ScreenDescriptor {
+            size_in_pixels,
+            pixels_per_point: self.ctx.pixels_per_point(),
+        };
+        for (id, delta) in &textures.set {
+            self.renderer.update_texture(device, queue, *id, delta);
+        }
+        self.renderer
+            .update_buffers(device, queue, encoder, &primitives, &screen);
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("UI Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            self.renderer.render(&mut render_pass, &primitives, &screen);
+        }
+        for id in &textures.free {
+            self.renderer.free_texture(id);
+        }
+    }
+}
+
+// compiler output in a closable window, false once it is closed
+fn error_window(ctx: &egui::Context, errors: &[&String]) -> bool {
+    let mut open = true;
+    egui::Window::new("Errors")
+        .open(&mut open)
+        .collapsible(false)
+        .anchor(egui::Align2::CENTER_TOP, [0.0, 40.0])
+        .default_width(640.0)
+        .show(ctx, |ui| {
+            egui::ScrollArea::vertical()
+                .max_height(400.0)
+                .show(ui, |ui| {
+                    for &error in errors {
+                        ui.label(
+                            egui::RichText::new(error)
+                                .monospace()
+                                .color(egui::Color32::LIGHT_RED),
+                        );
+                    }
+                });
+        });
+    open
+}
+
+// the outline as a collapsible tree; the section holding `page` is shown in bold
+fn outline_panel(ctx: &egui::Context, toc: &mut Toc, page: i32) -> Option<Action> {
+    let mut action = None;
+    let current = toc.current(page);
+    let scroll = toc.take_scroll();
+    egui::SidePanel::left("outline")
+        .resizable(true)
+        .default_width(240.0)
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.strong(if toc.is_generated() {
+                    "Generated outline"
+                } else {
+                    "Outline"
+                });
+                if !toc.is_empty() && ui.small_button("Export JSON").clicked() {
+                    action = Some(Action::ExportOutline);
+                }
+            });
+            ui.separator();
+            if toc.is_empty() {
+                ui.weak("no outline");
+                return;
+            }
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                for i in toc.visible() {
+                    let entry = &toc.entries()[i];
+                    let mut title = egui::RichText::new(&entry.title);
+                    if Some(i) == current {
+                        title = title.strong();
+                    }
+                    let depth = entry.depth as f32;
+                    let target = entry.page;
+                    ui.horizontal(|ui| {
+                        ui.add_space(depth * 12.0);
+                        if toc.has_children(i) {
+                            let arrow = if toc.is_expanded(i) { "v" } else { ">" };
+                            if ui.small_button(arrow).clicked() {
+                                toc.toggle(i);
+                            }
+                        } else {
+                            ui.add_space(ui.spacing().interact_size.y);
+                        }
+                        let row = ui.selectable_label(toc.selected() == Some(i), title);
+                        if row.clicked() {
+                            toc.select(i);
+                            action = target.map(|p| Action::Go(Command::Goto(p)));
+                        }
+                        if scroll && toc.selected() == Some(i) {
+                            row.scroll_to_me(None);
+                        }
+                    });
+                }
+            });
+        });
+    action
+}
@@ -0,0 +1,1019 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use egui_winit::winit;
+use egui_winit::winit::event::{
+    ElementState, ModifiersState, MouseButton, MouseScrollDelta, WindowEvent,
+};
+use mupdf::document::Document;
+
+use crate::cache::PageCache;
+use crate::camera::{Camera, ZoomSettle};
+use crate::layout::{self, Fit, Spreads, Strip, View};
+use crate::nav::Command;
+use crate::page::PageSlot;
+use crate::post::Post;
+use crate::raster::{Job, Options, Rasterizer, Rendered, Work};
+use crate::recolor::{Theme, Vision};
+use crate::redraw::Redraw;
+use crate::render::Renderer;
+use crate::texture;
+use crate::tiles::{self, Atlas, Placed};
+
+// raster scale at zoom 1
+const BASE_RES: f32 = 3.0;
+// free page textures kept for reuse
+const POOL_SIZE: usize = 4;
+// pixels scrolled per mouse wheel line in the continuous view
+const SCROLL_LINE: f32 = 60.0;
+// how far the cursor may move between press and release of a click
+const CLICK_SLOP: f64 = 4.0;
+// time between frames while a post-processing pass animates
+const POST_FRAME: Duration = Duration::from_millis(16);
+
+// how a viewer starts out. everything but the cache and downsampling can
+// be changed later through the viewer
+#[derive(Clone, Copy, Debug)]
+pub struct Settings {
+    pub fit: Fit,
+    pub view: View,
+    // spread options: a standalone first page and right to left order
+    pub cover: bool,
+    pub rtl: bool,
+    // around the pages, linear like the render target
+    pub background: wgpu::Color,
+    // colors the pages are drawn in
+    pub theme: Theme,
+    // the color vision filter over them
+    pub vision: Vision,
+    pub cache_bytes: u64,
+    pub downsample: texture::Downsample,
+    // physical pixels per logical pixel of the target
+    pub scale_factor: f64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            fit: Fit::Page,
+            view: View::Single,
+            cover: false,
+            rtl: false,
+            background: wgpu::Color::BLACK,
+            theme: Theme::Normal,
+            vision: Vision::Normal,
+            cache_bytes: 256 * 1024 * 1024,
+            downsample: texture::Downsample::Box,
+            scale_factor: 1.0,
+        }
+    }
+}
+
+// what a viewer has to tell the application about
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    // zero-based index of the page now current
+    PageChanged(i32),
+    LinkClicked(Link),
+}
+
+// where a clicked link points. links inside the document are followed by
+// the viewer itself; the rest are up to the application
+#[derive(Clone, Debug, PartialEq)]
+pub enum Link {
+    // zero-based page index
+    Page(i32),
+    Uri(String),
+}
+
+// a document on screen: the pages on the gpu, the raster thread feeding
+// them and where the view is. the device, queue and event loop belong to
+// the application, which hands it window events, calls `update` and
+// `draw` for each frame and asks `due`/`wake` when one is needed
+pub struct Viewer {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    format: wgpu::TextureFormat,
+    size: winit::dpi::PhysicalSize<u32>,
+    renderer: Renderer,
+    // the page shader's name and source when it was replaced, to
+    // replace it again on a new device
+    shader: Option<(String, String)>,
+    // the user's passes over the drawn pages, and when the viewer started
+    // for their clock
+    post: Post,
+    started: Instant,
+    // page textures on the gpu, and the ones drawn this frame in order
+    cache: PageCache,
+    shown: Vec<Job>,
+    // textures of evicted pages, reused for new ones
+    pool: texture::Pool,
+    converter: texture::Converter,
+    downsample: texture::Downsample,
+    // detail tiles for pages zoomed in past their raster, and the ones
+    // drawn this frame
+    atlas: Atlas,
+    tiles: Vec<Placed>,
+    rasterizer: Rasterizer,
+    // set by the raster thread when there are rasters to take
+    ready: Arc<AtomicBool>,
+    // work sent to the raster thread and not back yet
+    requested: Vec<Work>,
+    // work that failed, so it isn't asked for again
+    failed: Vec<Work>,
+    doc: Document,
+    page: i32,
+    page_count: i32,
+    camera: Camera,
+    fit: Fit,
+    view: View,
+    cover: bool,
+    rtl: bool,
+    scale_factor: f64,
+    // size of every page in points, loaded for the continuous view
+    page_sizes: Vec<(f32, f32)>,
+    strip: Strip,
+    // how far the continuous view is scrolled, in pixels at zoom 1
+    scroll: f32,
+    // the camera and scroll the slots and tiles were last picked for
+    synced: (Camera, f32),
+    modifiers: ModifiersState,
+    cursor: winit::dpi::PhysicalPosition<f64>,
+    // where the left button went down, while it is held
+    pressed: Option<winit::dpi::PhysicalPosition<f64>>,
+    zoom_settle: ZoomSettle,
+    res: f32,
+    options: Options,
+    color: wgpu::Color,
+    theme: Theme,
+    vision: Vision,
+    redraw: Redraw,
+    // the page last reported, and what hasn't been picked up yet
+    reported: i32,
+    events: Vec<Event>,
+}
+
+impl Viewer {
+    // open `filename` to draw into `size` pixels of `format` targets.
+    // `wake` is called from the raster thread whenever a page is ready,
+    // and should get the event loop to ask `due` again
+    pub fn new(
+        device: Arc<wgpu::Device>,
+        queue: Arc<wgpu::Queue>,
+        format: wgpu::TextureFormat,
+        filename: &str,
+        size: winit::dpi::PhysicalSize<u32>,
+        settings: Settings,
+        wake: impl Fn() + Send + 'static,
+    ) -> Result<Self> {
+        let doc =
+            Document::open(filename).with_context(|| format!("could not open {}", filename))?;
+        let page_count = doc.page_count()?;
+        let renderer = Renderer::new(&device, format);
+        let post = Post::new(&device, format);
+        let atlas = Atlas::new(&device, &renderer.texture_bind_group_layout);
+        let mipmapper = texture::Mipmapper::new(&device, settings.downsample);
+        let pool = texture::Pool::new(POOL_SIZE, Some(mipmapper));
+        let converter = texture::Converter::new(&device);
+        let ready = Arc::new(AtomicBool::new(false));
+        let flag = ready.clone();
+        let rasterizer = Rasterizer::spawn(filename.to_string(), move || {
+            flag.store(true, Ordering::Relaxed);
+            wake();
+        });
+
+        let mut viewer = Self {
+            device,
+            queue,
+            format,
+            size,
+            renderer,
+            shader: None,
+            post,
+            started: Instant::now(),
+            cache: PageCache::new(settings.cache_bytes),
+            shown: Vec::new(),
+            pool,
+            converter,
+            downsample: settings.downsample,
+            atlas,
+            tiles: Vec::new(),
+            rasterizer,
+            ready,
+            requested: Vec::new(),
+            failed: Vec::new(),
+            doc,
+            page: 0,
+            page_count,
+            camera: Camera::new(),
+            fit: settings.fit,
+            view: settings.view,
+            cover: settings.cover,
+            rtl: settings.rtl,
+            scale_factor: settings.scale_factor,
+            page_sizes: Vec::new(),
+            strip: Strip::default(),
+            scroll: 0.0,
+            synced: (Camera::new(), 0.0),
+            modifiers: ModifiersState::empty(),
+            cursor: winit::dpi::PhysicalPosition::new(0.0, 0.0),
+            pressed: None,
+            zoom_settle: ZoomSettle::default(),
+            res: BASE_RES,
+            options: Options {
+                images: settings.theme != Theme::Normal,
+                ..Options::default()
+            },
+            color: settings.background,
+            theme: settings.theme,
+            vision: settings.vision,
+            redraw: Redraw::default(),
+            reported: 0,
+            events: Vec::new(),
+        };
+        viewer.relayout();
+        viewer.sync_slots();
+        Ok(viewer)
+    }
+
+    pub fn document(&self) -> &Document {
+        &self.doc
+    }
+
+    pub fn page(&self) -> i32 {
+        self.page
+    }
+
+    pub fn page_count(&self) -> i32 {
+        self.page_count
+    }
+
+    pub fn view(&self) -> View {
+        self.view
+    }
+
+    pub fn size(&self) -> winit::dpi::PhysicalSize<u32> {
+        self.size
+    }
+
+    pub fn format(&self) -> wgpu::TextureFormat {
+        self.format
+    }
+
+    // everything that happened since the last call, oldest first
+    pub fn events(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.events)
+    }
+
+    // start over on a new device, after the old one was lost. pages are
+    // rasterized again as they are needed; the page, view and camera stay
+    // as they were
+    pub fn set_device(
+        &mut self,
+        device: Arc<wgpu::Device>,
+        queue: Arc<wgpu::Queue>,
+        format: wgpu::TextureFormat,
+    ) {
+        self.renderer = Renderer::new(&device, format);
+        if let Some((name, source)) = &self.shader {
+            // it compiled on the old device, so an error here is the new one's
+            if let Err(e) = self.renderer.set_shader(&device, name, source) {
+                eprintln!("{:#}", e);
+            }
+        }
+        // the passes compiled before, so they compile again
+        let sources = self.post.sources().to_vec();
+        self.post = Post::new(&device, format);
+        self.post.load(&device, &sources);
+        self.atlas = Atlas::new(&device, &self.renderer.texture_bind_group_layout);
+        let mipmapper = texture::Mipmapper::new(&device, self.downsample);
+        self.pool = texture::Pool::new(POOL_SIZE, Some(mipmapper));
+        self.converter = texture::Converter::new(&device);
+        self.device = device;
+        self.queue = queue;
+        self.format = format;
+        self.cache = PageCache::new(self.cache.budget());
+        self.shown.clear();
+        self.tiles.clear();
+        self.requested.clear();
+        self.failed.clear();
+        self.sync_slots();
+        self.redraw.request();
+    }
+
+    // the target changed size
+    pub fn resize(&mut self, size: winit::dpi::PhysicalSize<u32>) {
+        if size.width == 0 || size.height == 0 {
+            return;
+        }
+        self.size = size;
+        self.relayout();
+    }
+
+    // the monitor changed; actual-size pages have to follow it
+    pub fn rescale(&mut self, scale_factor: f64, size: winit::dpi::PhysicalSize<u32>) {
+        self.scale_factor = scale_factor;
+        self.resize(size);
+    }
+
+    // run `sources` over the pages from the next frame on, as pairs of a
+    // name and wgsl in the layout post.rs describes. passes that fail to
+    // compile are skipped, and their errors returned for the host to show
+    pub fn set_post(&mut self, sources: &[(String, String)]) -> Vec<String> {
+        let errors = self.post.load(&self.device, sources);
+        self.redraw.request();
+        errors
+    }
+
+    // draw pages with `source` instead of the built in shader.wgsl, for
+    // working on it without rebuilding. if it doesn't compile the pages
+    // are drawn as before and the error, naming `name`, is returned
+    pub fn set_shader(&mut self, name: &str, source: &str) -> Result<()> {
+        self.renderer.set_shader(&self.device, name, source)?;
+        self.shader = Some((name.to_string(), source.to_string()));
+        self.redraw.request();
+        Ok(())
+    }
+
+    // something outside the viewer changed what is on screen
+    pub fn request_redraw(&mut self) {
+        self.redraw.request();
+    }
+
+    // draw at `at` even if nothing else changes by then
+    pub fn request_redraw_at(&mut self, at: Instant) {
+        self.redraw.request_at(at);
+    }
+
+    // whether a frame is due at `now`
+    pub fn due(&mut self, now: Instant) -> bool {
+        if self.ready.swap(false, Ordering::Relaxed) {
+            self.redraw.request();
+        }
+        if let Some(at) = self.zoom_settle.at() {
+            self.redraw.request_at(at);
+        }
+        self.redraw.due(now)
+    }
+
+    // when the next frame is wanted if none is due now
+    pub fn wake(&self) -> Option<Instant> {
+        self.redraw.wake()
+    }
+
+    // frames drawn so far, which stops rising while nothing changes
+    pub fn frames(&self) -> u64 {
+        self.redraw.frames()
+    }
+
+    // rebuild the continuous strip for the current window and fit mode
+    fn relayout(&mut self) {
+        self.redraw.request();
+        if self.view == View::Continuous {
+            if self.page_sizes.is_empty() {
+                self.page_sizes = (0..self.page_count).map(|p| self.page_size(p)).collect();
+            }
+            // keep the same spot of the current page at the top of the window
+            let old_height = self.strip.page_height(self.page);
+            let into = self.scroll - self.strip.top(self.page);
+            self.strip = Strip::new(self.fit, &self.page_sizes, self.size, self.scale_factor);
+            let ratio = if old_height > 0.0 {
+                self.strip.page_height(self.page) / old_height
+            } else {
+                0.0
+            };
+            self.scroll = self.strip.top(self.page) + into * ratio;
+            self.clamp_scroll();
+        }
+        self.sync_slots();
+    }
+
+    // the band of the strip the window shows, in pixels at zoom 1,
+    // undoing the camera so zoomed out views pull in more pages
+    fn viewport(&self) -> (f32, f32) {
+        let h = self.size.height as f32;
+        let to_strip = |clip_y: f32| {
+            let y = (clip_y - self.camera.pos.y) / self.camera.zoom;
+            self.scroll + (1.0 - y) * h / 2.0
+        };
+        (to_strip(1.0), to_strip(-1.0))
+    }
+
+    // scroll the continuous view so strip height `y` is at the top of the window
+    fn scroll_to(&mut self, y: f32) {
+        let h = self.size.height as f32;
+        let top = (1.0 - self.camera.pos.y) / self.camera.zoom;
+        self.scroll = y - (1.0 - top) * h / 2.0;
+        self.clamp_scroll();
+    }
+
+    // the last page may be scrolled up to the top of the window
+    fn clamp_scroll(&mut self) {
+        let last = self.strip.top((self.page_count - 1).max(0));
+        let max = (self.strip.height() - self.size.height as f32).max(last);
+        self.scroll = self.scroll.clamp(0.0, max);
+    }
+
+    // the pages that should be on the gpu right now
+    fn wanted_pages(&self) -> std::ops::Range<i32> {
+        match self.view {
+            View::Single => self.page..self.page + 1,
+            View::Spread => {
+                let spreads = self.spreads();
+                spreads.pages(spreads.index(self.page))
+            }
+            View::Continuous => {
+                let (y0, y1) = self.viewport();
+                let visible = self.strip.visible(y0, y1);
+                visible.start.max(0)..visible.end.min(self.page_count)
+            }
+        }
+    }
+
+    fn spreads(&self) -> Spreads {
+        Spreads {
+            cover: self.cover,
+            page_count: self.page_count,
+        }
+    }
+
+    // where a slot's page belongs this frame. pages left over from the
+    // previous spread keep their place until replaced
+    fn slot_quad(&self, slot: &PageSlot) -> layout::Quad {
+        let page = slot.job.page;
+        match self.view {
+            View::Spread => {
+                let spreads = self.spreads();
+                let pages = spreads.pages(spreads.index(page));
+                let sizes: Vec<_> = pages.clone().map(|p| self.page_size(p)).collect();
+                let quads =
+                    layout::place_spread(self.fit, &sizes, self.size, self.scale_factor, self.rtl);
+                quads[(page - pages.start) as usize]
+            }
+            View::Single => {
+                let pixels =
+                    layout::page_pixels(self.fit, self.page_size(page), self.size, self.scale_factor);
+                layout::place(pixels, self.size)
+            }
+            View::Continuous => self.strip.quad(page, self.scroll, self.size),
+        }
+    }
+
+    fn place_slots(&self) {
+        for slot in self.shown.iter().filter_map(|job| self.cache.get(job)) {
+            slot.place(&self.queue, self.slot_quad(slot));
+        }
+    }
+
+    // tiles for the visible part of every page shown finer on screen
+    // than its raster, at the level matching the zoom. no more than half
+    // the atlas is asked for, so the tiles on screen never evict each other
+    fn wanted_tiles(&self) -> Vec<Placed> {
+        let camera = self.camera;
+        // the window in clip space before the camera
+        let view = layout::Quad {
+            left: (-1.0 - camera.pos.x) / camera.zoom,
+            top: (1.0 - camera.pos.y) / camera.zoom,
+            right: (1.0 - camera.pos.x) / camera.zoom,
+            bottom: (-1.0 - camera.pos.y) / camera.zoom,
+        };
+        let mut placed = Vec::new();
+        for slot in self.shown.iter().filter_map(|job| self.cache.get(job)) {
+            let quad = self.slot_quad(slot);
+            let (w, h) = (quad.right - quad.left, quad.top - quad.bottom);
+            // pixels per point the page is drawn at
+            let scale = h / 2.0 * self.size.height as f32 * camera.zoom / slot.size.1.max(1.0);
+            if scale <= slot.job.res || w <= 0.0 || h <= 0.0 {
+                continue;
+            }
+            let area = layout::Quad {
+                left: ((view.left - quad.left) / w).clamp(0.0, 1.0),
+                top: ((quad.top - view.top) / h).clamp(0.0, 1.0),
+                right: ((view.right - quad.left) / w).clamp(0.0, 1.0),
+                bottom: ((quad.top - view.bottom) / h).clamp(0.0, 1.0),
+            };
+            if area.left >= area.right || area.top >= area.bottom {
+                continue;
+            }
+            let level = tiles::level_for(slot.size, scale);
+            let page = slot.job.page;
+            for tile in tiles::covering(page, slot.size, level, self.options, area) {
+                placed.push(Placed {
+                    tile,
+                    size: slot.size,
+                    quad,
+                });
+            }
+        }
+        placed.truncate(self.atlas.capacity() / 2);
+        placed
+    }
+
+    // size of `page` in points, letter size if it cannot be loaded
+    fn page_size(&self, page: i32) -> (f32, f32) {
+        if let Some(&size) = self.page_sizes.get(page as usize) {
+            return size;
+        }
+        if let Some(slot) = self.cache.any(page) {
+            return slot.size;
+        }
+        self.doc
+            .load_page(page)
+            .and_then(|p| p.bounds())
+            .map_or((612.0, 792.0), |b| (b.width(), b.height()))
+    }
+
+    // switch between `view` and single pages, keeping the page
+    pub fn toggle_view(&mut self, view: View) -> View {
+        self.view = if self.view == view { View::Single } else { view };
+        if self.view == View::Spread {
+            self.page = self.spread_start(self.page);
+        }
+        self.camera.reset();
+        self.scroll = 0.0;
+        self.strip = Strip::default();
+        self.relayout();
+        if self.view == View::Continuous {
+            self.scroll_to(self.strip.top(self.page));
+        }
+        self.sync_slots();
+        self.view
+    }
+
+    pub fn toggle_cover(&mut self) -> bool {
+        self.cover = !self.cover;
+        if self.view == View::Spread {
+            self.page = self.spread_start(self.page);
+            self.sync_slots();
+        }
+        self.redraw.request();
+        self.cover
+    }
+
+    pub fn toggle_rtl(&mut self) -> bool {
+        self.rtl = !self.rtl;
+        self.sync_slots();
+        self.redraw.request();
+        self.rtl
+    }
+
+    // the first page of the spread holding `page`
+    fn spread_start(&self, page: i32) -> i32 {
+        let spreads = self.spreads();
+        spreads.start(spreads.index(page))
+    }
+
+    pub fn toggle_annotations(&mut self) -> bool {
+        self.options.annotations = !self.options.annotations;
+        self.sync_slots();
+        self.options.annotations
+    }
+
+    // the next color theme; pages are recolored as they are drawn
+    pub fn cycle_theme(&mut self) -> Theme {
+        self.theme = self.theme.next();
+        // masks for the images are only made while a theme needs them
+        let images = self.theme != Theme::Normal;
+        if images != self.options.images {
+            self.options.images = images;
+            self.sync_slots();
+        }
+        self.redraw.request();
+        self.theme
+    }
+
+    // the next color vision filter, applied as pages are drawn and to
+    // exports alike
+    pub fn cycle_vision(&mut self) -> Vision {
+        self.vision = self.vision.next();
+        self.redraw.request();
+        self.vision
+    }
+
+    pub fn cycle_fit(&mut self) -> Fit {
+        self.fit = self.fit.next();
+        self.camera.reset();
+        self.zoom_settle.changed();
+        self.relayout();
+        self.fit
+    }
+
+    // back to the whole page, unzoomed
+    pub fn reset_zoom(&mut self) {
+        self.camera.reset();
+        self.zoom_settle.changed();
+        self.redraw.request();
+    }
+
+    // handle a window event meant for the viewer, returning whether it
+    // was used. keys are left to the application to bind
+    pub fn input(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                if self.pressed.is_some() {
+                    let dx = (position.x - self.cursor.x) as f32;
+                    let dy = (position.y - self.cursor.y) as f32;
+                    self.camera.pan(dx, dy, self.size);
+                    self.redraw.request();
+                }
+                // passes may follow the cursor
+                if !self.post.is_empty() {
+                    self.redraw.request();
+                }
+                self.cursor = *position;
+                true
+            }
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } => {
+                match state {
+                    ElementState::Pressed => self.pressed = Some(self.cursor),
+                    ElementState::Released => {
+                        // a press and release in the same spot is a click,
+                        // anything else was a drag
+                        if let Some(at) = self.pressed.take() {
+                            let moved = (at.x - self.cursor.x).hypot(at.y - self.cursor.y);
+                            if moved <= CLICK_SLOP {
+                                self.click(at);
+                            }
+                        }
+                    }
+                }
+                true
+            }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = *modifiers;
+                false
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let lines = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(p) => p.y as f32 / SCROLL_LINE,
+                };
+                // the continuous view scrolls, unless ctrl is held
+                if self.view == View::Continuous && !self.modifiers.ctrl() {
+                    self.scroll -= lines * SCROLL_LINE / self.camera.zoom;
+                    self.clamp_scroll();
+                    self.redraw.request();
+                } else {
+                    self.zoom(1.1f32.powf(lines));
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // the page under window pixel `at`, and the point on it in page space
+    fn page_point(&self, at: winit::dpi::PhysicalPosition<f64>) -> Option<(i32, f32, f32)> {
+        let clip_x = at.x as f32 / self.size.width as f32 * 2.0 - 1.0;
+        let clip_y = 1.0 - at.y as f32 / self.size.height as f32 * 2.0;
+        // undo the camera, like the viewport does
+        let x = (clip_x - self.camera.pos.x) / self.camera.zoom;
+        let y = (clip_y - self.camera.pos.y) / self.camera.zoom;
+        self.shown
+            .iter()
+            .filter_map(|job| self.cache.get(job))
+            .find_map(|slot| {
+                let quad = self.slot_quad(slot);
+                let inside =
+                    (quad.left..quad.right).contains(&x) && (quad.bottom..quad.top).contains(&y);
+                inside.then(|| {
+                    let u = (x - quad.left) / (quad.right - quad.left);
+                    let v = (quad.top - y) / (quad.top - quad.bottom);
+                    (slot.job.page, u * slot.size.0, v * slot.size.1)
+                })
+            })
+    }
+
+    // follow the link under a click, if there is one
+    fn click(&mut self, at: winit::dpi::PhysicalPosition<f64>) {
+        let Some((page, x, y)) = self.page_point(at) else {
+            return;
+        };
+        let link = self.doc.load_page(page).and_then(|page| {
+            // links are in page space, which need not start at the origin
+            let bounds = page.bounds()?;
+            let (x, y) = (bounds.x0 + x, bounds.y0 + y);
+            Ok(page.links()?.find(|link| {
+                let b = &link.bounds;
+                (b.x0..=b.x1).contains(&x) && (b.y0..=b.y1).contains(&y)
+            }))
+        });
+        let link = match link {
+            Ok(Some(link)) => link,
+            Ok(None) => return,
+            Err(e) => {
+                eprintln!("could not load the links of page {}: {:?}", page + 1, e);
+                return;
+            }
+        };
+        // mupdf resolves links within the document to a page and leaves
+        // the rest as they are
+        let link = if link.uri.starts_with('#') {
+            Link::Page(link.page as i32)
+        } else {
+            Link::Uri(link.uri)
+        };
+        if let Link::Page(page) = link {
+            self.execute(Command::Goto(page));
+        }
+        self.events.push(Event::LinkClicked(link));
+    }
+
+    // zoom around the cursor, re-rasterizing once zooming settles
+    fn zoom(&mut self, factor: f32) {
+        self.camera.zoom_at(factor, self.cursor, self.size);
+        self.zoom_settle.changed();
+        self.redraw.request();
+    }
+
+    // zoom around the window center, for keyboard zoom
+    pub fn zoom_center(&mut self, factor: f32) {
+        self.cursor = winit::dpi::PhysicalPosition::new(
+            self.size.width as f64 / 2.0,
+            self.size.height as f64 / 2.0,
+        );
+        self.zoom(factor);
+    }
+
+    // the raster scale for the current zoom; `job` keeps each page's
+    // within the gpu's texture limit
+    fn wanted_res(&self) -> f32 {
+        BASE_RES * self.camera.zoom
+    }
+
+    // re-rasterize at the zoomed resolution once the zoom has been still for a moment
+    fn settle_zoom(&mut self) {
+        if !self.zoom_settle.settled() {
+            return;
+        }
+        let res = self.wanted_res();
+        if (res - self.res).abs() > 0.01 {
+            self.res = res;
+            self.sync_slots();
+        }
+    }
+
+    // get ready to draw a frame: take in the pages that came back, follow
+    // the scroll and upload what the camera now needs
+    pub fn update(&mut self) {
+        // taken before the frame starts, since this frame shows them
+        while let Some(rendered) = self.rasterizer.try_recv() {
+            self.receive(rendered);
+        }
+        self.redraw.start();
+        self.settle_zoom();
+        if self.view == View::Continuous {
+            // the current page is the one under a line a quarter of the way
+            // down the window, so a page scrolled to the top is current
+            let (y0, y1) = self.viewport();
+            let line = y0 + (y1 - y0) / 4.0;
+            self.page = self.strip.page_at(line).clamp(0, (self.page_count - 1).max(0));
+        }
+        self.report_page();
+        // everything else that moves the pages syncs them as it happens;
+        // tiles and the continuous view's pages also follow the camera
+        if self.synced != (self.camera, self.scroll) {
+            self.sync_slots();
+        }
+        self.atlas.prepare(&self.device, &self.queue, &self.tiles);
+        self.renderer.set_camera(&self.queue, &self.camera);
+        self.renderer.set_theme(&self.queue, self.theme, self.vision);
+        if !self.post.is_empty() {
+            let (width, height) = (self.size.width, self.size.height);
+            let page_size = self.page_size(self.page);
+            self.post.resize(&self.device, width, height);
+            self.post.set_uniform(
+                &self.queue,
+                [width as f32, height as f32],
+                [page_size.0, page_size.1],
+                [self.cursor.x as f32, self.cursor.y as f32],
+                self.started.elapsed().as_secs_f32(),
+            );
+        }
+    }
+
+    // draw the pages into `view`, a target of the viewer's format and
+    // size, through the post-processing passes if there are any
+    pub fn draw(&mut self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        if self.post.is_empty() {
+            self.draw_pages(encoder, view);
+        } else {
+            self.draw_pages(encoder, self.post.input());
+            self.post.run(encoder, view);
+        }
+        if self.post.animated() {
+            self.redraw.request_at(Instant::now() + POST_FRAME);
+        }
+        self.redraw.finish();
+    }
+
+    // draw the pages as they are on screen into another target, for
+    // snapshots that shouldn't count as a frame
+    pub fn draw_pages(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        let slots = self.shown.iter().filter_map(|job| self.cache.get(job));
+        self.renderer
+            .draw(encoder, view, self.color, slots, &self.atlas);
+    }
+
+    fn report_page(&mut self) {
+        if self.page != self.reported {
+            self.reported = self.page;
+            self.events.push(Event::PageChanged(self.page));
+        }
+    }
+
+    // run a navigation command, re-rendering only if the page changed.
+    // spreads step a whole spread at a time, counts included
+    pub fn execute(&mut self, cmd: Command) {
+        let page = match (self.view, cmd) {
+            (View::Spread, Command::Goto(_)) => {
+                self.spread_start(cmd.apply(self.page, self.page_count))
+            }
+            (View::Spread, _) => {
+                let spreads = self.spreads();
+                let index = cmd.apply(spreads.index(self.page), spreads.count());
+                spreads.start(index)
+            }
+            _ => cmd.apply(self.page, self.page_count),
+        };
+        if page == self.page {
+            return;
+        }
+        self.page = page;
+        if self.view == View::Continuous {
+            self.scroll_to(self.strip.top(page));
+        }
+        self.report_page();
+        self.sync_slots();
+        self.redraw.request();
+    }
+
+    // the job for `page` at the current resolution and options
+    // the raster for `page` at the current resolution, or as close as
+    // the gpu's texture limit allows for a page that size
+    fn job(&self, page: i32) -> Job {
+        let limit = self.device.limits().max_texture_dimension_2d;
+        Job {
+            page,
+            res: self.res.min(max_res(self.page_size(page), limit)),
+            options: self.options,
+        }
+    }
+
+    // pages just past the ones on screen, rendered ahead of time so
+    // turning to them is instant. the next page comes first
+    fn neighbour_pages(&self) -> Vec<i32> {
+        let wanted = self.wanted_pages();
+        let pages: Vec<i32> = match self.view {
+            View::Spread => {
+                let spreads = self.spreads();
+                let index = spreads.index(self.page);
+                spreads.pages(index + 1).chain(spreads.pages(index - 1)).collect()
+            }
+            _ => vec![wanted.end, wanted.start - 1],
+        };
+        pages
+            .into_iter()
+            .filter(|p| (0..self.page_count).contains(p) && !wanted.contains(p))
+            .collect()
+    }
+
+    // pick what to draw for the wanted pages and queue what is missing.
+    // a page without its exact raster stands in with another one of the
+    // same page, and if there is none the previous pages stay up until
+    // the raster arrives. neighbours are queued behind the wanted pages
+    fn sync_slots(&mut self) {
+        self.synced = (self.camera, self.scroll);
+        let wanted = self.wanted_pages();
+        let mut shown = Vec::new();
+        let mut missing = Vec::new();
+        let mut uncovered = false;
+        for page in wanted.clone() {
+            let job = self.job(page);
+            if self.cache.contains(&job) {
+                shown.push(job);
+                continue;
+            }
+            if self.failed.contains(&Work::Page(job)) {
+                continue;
+            }
+            missing.push(job);
+            match self.cache.any(page) {
+                Some(slot) => shown.push(slot.job),
+                None => uncovered = true,
+            }
+        }
+        if uncovered {
+            let stale = self
+                .shown
+                .iter()
+                .filter(|job| !wanted.contains(&job.page) && self.cache.contains(job));
+            shown = stale.copied().chain(shown).collect();
+        }
+        for job in &shown {
+            self.cache.touch(job);
+        }
+        self.shown = shown;
+
+        self.tiles = self.wanted_tiles();
+        // parents go first: each one covers four tiles at half the detail
+        let mut tiles: Vec<Work> = Vec::new();
+        let parents = self.tiles.iter().filter_map(|p| p.tile.parent());
+        for tile in parents.chain(self.tiles.iter().map(|p| p.tile)) {
+            let work = Work::Tile(tile);
+            if !self.atlas.contains(&tile) && !self.failed.contains(&work) && !tiles.contains(&work)
+            {
+                tiles.push(work);
+            }
+        }
+
+        let prefetch = self
+            .neighbour_pages()
+            .into_iter()
+            .map(|page| self.job(page))
+            .filter(|job| !self.cache.contains(job) && !self.failed.contains(&Work::Page(*job)))
+            .map(Work::Page);
+        let jobs: Vec<Work> = missing
+            .into_iter()
+            .map(Work::Page)
+            .chain(tiles)
+            .chain(prefetch)
+            .collect();
+        if jobs != self.requested {
+            self.rasterizer.request(jobs.clone());
+            self.requested = jobs;
+        }
+        self.place_slots();
+    }
+
+    // a raster came back from the raster thread
+    fn receive(&mut self, rendered: Rendered) {
+        let Rendered { work, raster } = rendered;
+        // superseded while it was being drawn
+        if !self.requested.contains(&work) {
+            return;
+        }
+        self.requested.retain(|w| *w != work);
+        self.redraw.request();
+        let result = raster.and_then(|raster| match work {
+            Work::Page(job) => {
+                let slot = PageSlot::new(
+                    &self.device,
+                    &self.queue,
+                    &self.converter,
+                    &self.renderer.texture_bind_group_layout,
+                    &mut self.pool,
+                    job,
+                    &raster,
+                )?;
+                for old in self.cache.insert(slot, &self.shown) {
+                    self.pool.recycle(old.texture);
+                }
+                Ok(())
+            }
+            Work::Tile(tile) => {
+                self.atlas
+                    .insert(&self.device, &self.queue, &self.converter, tile, &raster);
+                Ok(())
+            }
+        });
+        if let Err(e) = result {
+            let page = match work {
+                Work::Page(job) => job.page,
+                Work::Tile(tile) => tile.page,
+            };
+            eprintln!("could not render page {}: {:?}", page + 1, e);
+            self.failed.push(work);
+        }
+        self.sync_slots();
+    }
+}
+
+// the largest raster scale at which a page of `size` points fits in
+// `limit` texels on its longer side
+fn max_res(size: (f32, f32), limit: u32) -> f32 {
+    limit as f32 / size.0.max(size.1).max(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oversized_pages_fit_the_texture_limit() {
+        // a0 in points
+        let a0 = (2384.0, 3370.0);
+        let res = BASE_RES.min(max_res(a0, 8192));
+        assert!(res < BASE_RES);
+        assert!((a0.1 * res).ceil() <= 8192.0);
+        assert!((a0.0 * res).ceil() <= 8192.0);
+        // letter fits at the base resolution
+        assert_eq!(BASE_RES.min(max_res((612.0, 792.0), 8192)), BASE_RES);
+    }
+}
@@ -0,0 +1,49 @@
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+// how often the files are looked at
+const POLL: Duration = Duration::from_millis(250);
+
+// notices when files change on disk, for reloading shaders while working
+// on them. a thread compares modification times, which catches editors
+// that write in place and ones that replace the file alike
+pub struct Watcher {
+    changed: Receiver<PathBuf>,
+}
+
+impl Watcher {
+    // `wake` is called after each change is ready to take, like the
+    // raster thread's
+    pub fn spawn(paths: Vec<PathBuf>, wake: impl Fn() + Send + 'static) -> Self {
+        let (tx, changed) = mpsc::channel();
+        thread::Builder::new()
+            .name("watch".into())
+            .spawn(move || {
+                let modified = |path: &PathBuf| path.metadata().and_then(|m| m.modified()).ok();
+                let mut seen: Vec<Option<SystemTime>> = paths.iter().map(modified).collect();
+                loop {
+                    thread::sleep(POLL);
+                    for (path, seen) in paths.iter().zip(&mut seen) {
+                        let now = modified(path);
+                        // a file being replaced is briefly missing
+                        if now.is_some() && now != *seen {
+                            if tx.send(path.clone()).is_err() {
+                                return;
+                            }
+                            wake();
+                        }
+                        *seen = now.or(*seen);
+                    }
+                }
+            })
+            .expect("could not start the watch thread");
+        Self { changed }
+    }
+
+    // the next file that changed, if there is one
+    pub fn try_recv(&self) -> Option<PathBuf> {
+        self.changed.try_recv().ok()
+    }
+}
@@ -0,0 +1,38 @@
+use anyhow::{anyhow, bail, Result};
+
+// wgsl that isn't compiled into the binary has to fail without taking
+// the viewer down. naga checks it first, for errors that point into the
+// source; what only wgpu can tell, like bindings that don't match a
+// pipeline layout, is caught in an error scope
+
+// parse and validate `source`, with errors rendered against it under `name`
+pub fn validate(name: &str, source: &str) -> Result<naga::Module> {
+    let module = naga::front::wgsl::parse_str(source)
+        .map_err(|e| anyhow!("{}", e.emit_to_string_with_path(source, name)))?;
+    naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::empty(),
+    )
+    .validate(&module)
+    .map_err(|e| anyhow!("{}", e.emit_to_string_with_path(source, name)))?;
+    Ok(module)
+}
+
+// whether `module` has a `stage` entry point called `name`
+pub fn has_entry_point(module: &naga::Module, name: &str, stage: naga::ShaderStage) -> bool {
+    module
+        .entry_points
+        .iter()
+        .any(|e| e.name == name && e.stage == stage)
+}
+
+// run `create`, turning the validation errors wgpu raises meanwhile into
+// an Err instead of a panic
+pub fn checked<T>(device: &wgpu::Device, create: impl FnOnce() -> T) -> Result<T> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let created = create();
+    if let Some(e) = pollster::block_on(device.pop_error_scope()) {
+        bail!("{}", e);
+    }
+    Ok(created)
+}
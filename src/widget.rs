@@ -0,0 +1,375 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use egui::{pos2, vec2, Color32, Pos2, Rect, Sense, Vec2};
+use mupdf::document::Document;
+use mupdf::TextPageOptions;
+
+use crate::camera::{ZoomSettle, MAX_ZOOM, MIN_ZOOM};
+use crate::raster::{Job, Options, Raster, Rasterizer, Work};
+use crate::texture::{self, Converter, Mipmapper};
+
+// what selected text is highlighted with
+const SELECTION: Color32 = Color32::from_rgba_premultiplied(0, 60, 120, 90);
+
+// a character on the page and its box, in points from the page's corner
+struct Glyph {
+    c: char,
+    rect: Rect,
+}
+
+// the page texture egui is drawing: the job it was rasterized for, kept
+// alive while it is registered
+struct Shown {
+    job: Job,
+    id: egui::TextureId,
+    _texture: texture::Texture,
+}
+
+// one page of a document inside an egui layout, for applications that
+// already draw egui with egui-wgpu. the page scrolls within whatever space
+// the layout gives it, zooms with ctrl + wheel or a pinch around the
+// pointer, and text is selected by dragging across it and copied with
+// the usual shortcut. rasters are made off the ui thread and handed to
+// the egui renderer as native textures
+pub struct PdfView {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    renderer: Arc<egui::mutex::RwLock<egui_wgpu::Renderer>>,
+    converter: Converter,
+    mipmapper: Mipmapper,
+    rasterizer: Rasterizer,
+    doc: Document,
+    // tells views of the same document apart in egui's memory
+    id: egui::Id,
+    page: i32,
+    page_count: i32,
+    // page size in points
+    size: (f32, f32),
+    // egui points per page point
+    zoom: f32,
+    zoom_settle: ZoomSettle,
+    // the raster asked for last and the one on screen
+    wanted: Option<Job>,
+    shown: Option<Shown>,
+    // scroll position to apply on the next frame, after zooming
+    offset: Option<Vec2>,
+    // text of the current page, loaded when a selection starts
+    glyphs: Option<Vec<Glyph>>,
+    // corners of the selection rectangle, in page points
+    selection: Option<(Pos2, Pos2)>,
+    // what failed since the host last took them
+    errors: Vec<String>,
+}
+
+impl PdfView {
+    // open `filename` on the device and renderer egui is drawn with
+    pub fn new(
+        render_state: &egui_wgpu::RenderState,
+        ctx: &egui::Context,
+        filename: &str,
+    ) -> Result<Self> {
+        let doc =
+            Document::open(filename).with_context(|| format!("could not open {}", filename))?;
+        let page_count = doc.page_count()?;
+        let device = render_state.device.clone();
+        let ctx = ctx.clone();
+        let mut view = Self {
+            converter: Converter::new(&device),
+            mipmapper: Mipmapper::new(&device, texture::Downsample::Box),
+            rasterizer: Rasterizer::spawn(filename.to_string(), move || ctx.request_repaint()),
+            device,
+            queue: render_state.queue.clone(),
+            renderer: render_state.renderer.clone(),
+            doc,
+            id: egui::Id::new(("pdf_view", filename)),
+            page: 0,
+            page_count,
+            size: (612.0, 792.0),
+            zoom: 1.0,
+            zoom_settle: ZoomSettle::default(),
+            wanted: None,
+            shown: None,
+            offset: None,
+            glyphs: None,
+            selection: None,
+            errors: Vec::new(),
+        };
+        view.set_page(0);
+        Ok(view)
+    }
+
+    pub fn page(&self) -> i32 {
+        self.page
+    }
+
+    pub fn page_count(&self) -> i32 {
+        self.page_count
+    }
+
+    // show `page`, clamped to the document, from its top
+    pub fn set_page(&mut self, page: i32) {
+        self.page = page.clamp(0, (self.page_count - 1).max(0));
+        self.size = self
+            .doc
+            .load_page(self.page)
+            .and_then(|p| p.bounds())
+            .map_or((612.0, 792.0), |b| (b.width(), b.height()));
+        self.offset = Some(Vec2::ZERO);
+        self.glyphs = None;
+        self.selection = None;
+    }
+
+    pub fn zoom(&self) -> f32 {
+        self.zoom
+    }
+
+    pub fn set_zoom(&mut self, zoom: f32) {
+        self.zoom = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+        self.zoom_settle.changed();
+    }
+
+    // the text inside the selection, line by line
+    pub fn selected_text(&self) -> Option<String> {
+        let (a, b) = self.selection?;
+        let area = Rect::from_two_pos(a, b);
+        let mut text = String::new();
+        for glyph in self.glyphs.as_ref()? {
+            if glyph.c == '\n' {
+                if !text.is_empty() && !text.ends_with('\n') {
+                    text.push('\n');
+                }
+            } else if area.contains(glyph.rect.center()) {
+                text.push(glyph.c);
+            }
+        }
+        let text = text.trim_end().to_string();
+        (!text.is_empty()).then_some(text)
+    }
+
+    // pages that could not be rendered or have their text read, as
+    // messages for the host to show. taking them clears them
+    pub fn take_errors(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.errors)
+    }
+
+    // every character of the page, with a newline after each line
+    fn load_glyphs(&self) -> Result<Vec<Glyph>> {
+        let page = self.doc.load_page(self.page)?;
+        let bounds = page.bounds()?;
+        let text_page = page.to_text_page(TextPageOptions::empty())?;
+        // glyph quads are in page space, which need not start at the origin
+        let origin = vec2(bounds.x0, bounds.y0);
+        let mut glyphs = Vec::new();
+        for block in text_page.blocks() {
+            for line in block.lines() {
+                for c in line.chars() {
+                    let Some(ch) = c.char() else {
+                        continue;
+                    };
+                    let q = c.quad();
+                    let points = [q.ul, q.ur, q.ll, q.lr].map(|p| pos2(p.x, p.y) - origin);
+                    glyphs.push(Glyph {
+                        c: ch,
+                        rect: Rect::from_points(&points),
+                    });
+                }
+                glyphs.push(Glyph {
+                    c: '\n',
+                    rect: Rect::NOTHING,
+                });
+            }
+        }
+        Ok(glyphs)
+    }
+
+    // the raster scale for the zoom at `pixels_per_point`, within the
+    // gpu's texture limit
+    fn wanted_res(&self, pixels_per_point: f32) -> f32 {
+        let limit = self.device.limits().max_texture_dimension_2d as f32;
+        let max_res = limit / self.size.0.max(self.size.1).max(1.0);
+        (self.zoom * pixels_per_point).min(max_res)
+    }
+
+    // ask for the current page at the current zoom, once zooming settles
+    fn request(&mut self, ctx: &egui::Context) {
+        if let Some(at) = self.zoom_settle.at() {
+            if !self.zoom_settle.settled() {
+                ctx.request_repaint_after(at.saturating_duration_since(Instant::now()));
+                return;
+            }
+        }
+        let job = Job {
+            page: self.page,
+            res: self.wanted_res(ctx.pixels_per_point()),
+            options: Options::default(),
+        };
+        if self.wanted != Some(job) {
+            self.wanted = Some(job);
+            self.rasterizer.request(vec![Work::Page(job)]);
+        }
+    }
+
+    // take the rasters that came back, keeping the one asked for last
+    fn receive(&mut self) {
+        while let Some(rendered) = self.rasterizer.try_recv() {
+            if Some(rendered.work) != self.wanted.map(Work::Page) {
+                continue;
+            }
+            let Work::Page(job) = rendered.work else {
+                continue;
+            };
+            let result = rendered.raster.and_then(|raster| self.upload(job, &raster));
+            if let Err(e) = result {
+                self.errors
+                    .push(format!("could not render page {}: {:#}", job.page + 1, e));
+            }
+        }
+    }
+
+    // put `raster` on the gpu and show it in place of the last one
+    fn upload(&mut self, job: Job, raster: &Raster) -> Result<()> {
+        let label = format!("pdf view page{}", job.page);
+        let texture = texture::Texture::from_bytes(
+            &self.device,
+            &self.queue,
+            &self.converter,
+            &raster.pixels(),
+            &label,
+            Some(&self.mipmapper),
+        )?;
+        // trilinear, like the viewer's own pages, so zooming out stays smooth
+        let sampler = wgpu::SamplerDescriptor {
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            anisotropy_clamp: 16,
+            ..Default::default()
+        };
+        let mut renderer = self.renderer.write();
+        let id = match &self.shown {
+            Some(shown) => {
+                renderer.update_egui_texture_from_wgpu_texture_with_sampler_options(
+                    &self.device,
+                    &texture.view,
+                    sampler,
+                    shown.id,
+                );
+                shown.id
+            }
+            None => renderer.register_native_texture_with_sampler_options(
+                &self.device,
+                &texture.view,
+                sampler,
+            ),
+        };
+        self.shown = Some(Shown {
+            job,
+            id,
+            _texture: texture,
+        });
+        Ok(())
+    }
+
+    // zoom by `factor` keeping the page point under `pointer` in place.
+    // `viewport` is the scroll area on screen, scrolled by `offset`
+    fn zoom_at(&mut self, factor: f32, pointer: Pos2, viewport: Rect, offset: Vec2) {
+        let old = self.zoom;
+        self.set_zoom(old * factor);
+        let at = pointer - viewport.min;
+        self.offset = Some((offset + at) * (self.zoom / old) - at);
+    }
+}
+
+impl egui::Widget for &mut PdfView {
+    fn ui(self, ui: &mut egui::Ui) -> egui::Response {
+        self.receive();
+        self.request(ui.ctx());
+
+        let mut area = egui::ScrollArea::both()
+            .id_source(self.id)
+            .auto_shrink([false; 2])
+            // dragging selects text instead
+            .drag_to_scroll(false);
+        if let Some(offset) = self.offset.take() {
+            area = area.scroll_offset(offset);
+        }
+        let output = area.show(ui, |ui| {
+            let size = vec2(self.size.0, self.size.1) * self.zoom;
+            let (rect, response) = ui.allocate_exact_size(size, Sense::click_and_drag());
+            let to_page = |p: Pos2| ((p - rect.min) / self.zoom).to_pos2();
+            let to_screen = |r: Rect| {
+                Rect::from_min_max(
+                    rect.min + r.min.to_vec2() * self.zoom,
+                    rect.min + r.max.to_vec2() * self.zoom,
+                )
+            };
+
+            // rasters have transparent paper, like in the viewer
+            let painter = ui.painter_at(rect);
+            painter.rect_filled(rect, 0.0, Color32::WHITE);
+            if let Some(shown) = self.shown.as_ref().filter(|s| s.job.page == self.page) {
+                let uv = Rect::from_min_max(Pos2::ZERO, pos2(1.0, 1.0));
+                painter.image(shown.id, rect, uv, Color32::WHITE);
+            }
+
+            if response.drag_started_by(egui::PointerButton::Primary) {
+                if let Some(p) = response.interact_pointer_pos() {
+                    if self.glyphs.is_none() {
+                        // a page whose text can't be read selects nothing
+                        let glyphs = self.load_glyphs().unwrap_or_else(|e| {
+                            let page = self.page + 1;
+                            self.errors
+                                .push(format!("could not load the text of page {}: {:#}", page, e));
+                            Vec::new()
+                        });
+                        self.glyphs = Some(glyphs);
+                    }
+                    self.selection = Some((to_page(p), to_page(p)));
+                }
+            } else if response.dragged_by(egui::PointerButton::Primary) {
+                if let (Some(p), Some((_, end))) =
+                    (response.interact_pointer_pos(), self.selection.as_mut())
+                {
+                    *end = to_page(p);
+                }
+            } else if response.clicked() {
+                self.selection = None;
+            }
+
+            if let (Some((a, b)), Some(glyphs)) = (self.selection, &self.glyphs) {
+                let area = Rect::from_two_pos(a, b);
+                for glyph in glyphs.iter().filter(|g| area.contains(g.rect.center())) {
+                    painter.rect_filled(to_screen(glyph.rect), 0.0, SELECTION);
+                }
+            }
+            response
+        });
+        let response = output.inner;
+
+        // ctrl + wheel and pinches arrive as a zoom factor
+        let factor = ui.input(|i| i.zoom_delta());
+        if factor != 1.0 && response.hovered() {
+            if let Some(pointer) = ui.input(|i| i.pointer.hover_pos()) {
+                self.zoom_at(factor, pointer, output.inner_rect, output.state.offset);
+                ui.ctx().request_repaint();
+            }
+        }
+        let copy = ui.input(|i| i.events.iter().any(|e| matches!(e, egui::Event::Copy)));
+        if copy && response.hovered() {
+            if let Some(text) = self.selected_text() {
+                ui.ctx().copy_text(text);
+            }
+        }
+        response
+    }
+}
+
+impl Drop for PdfView {
+    fn drop(&mut self) {
+        if let Some(shown) = &self.shown {
+            self.renderer.write().free_texture(&shown.id);
+        }
+    }
+}
@@ -0,0 +1,665 @@
+// renders pages through the real page pipeline into an offscreen texture on
+// a software adapter, and compares them with the pngs in tests/golden.
+// XJ9_BLESS=1 writes the references; without it a missing one fails
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use egui_winit::winit::dpi::PhysicalSize;
+
+use pdf_rust::test_support::{self as parts, Camera, Job, Options, Post, Raster, Renderer};
+use pdf_rust::{Downsample, Fit, Offscreen, Settings, Theme, Viewer, Vision};
+
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+// a pixel differs when a channel is off by more than this
+const TOLERANCE: u8 = 16;
+// and an image matches when at most this share of its pixels differ
+const MAX_DIFFERENT: f64 = 0.005;
+
+struct Gpu {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    renderer: Renderer,
+    converter: parts::Converter,
+    pool: parts::Pool,
+}
+
+// the software adapter, or None where there isn't one
+fn gpu() -> Option<Gpu> {
+    gpu_with(|_| {})
+}
+
+// the software adapter with `limit` applied to its limits
+fn gpu_with(limit: impl FnOnce(&mut wgpu::Limits)) -> Option<Gpu> {
+    pollster::block_on(async {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                force_fallback_adapter: true,
+                ..Default::default()
+            })
+            .await?;
+        let mut limits = wgpu::Limits::downlevel_defaults().using_resolution(adapter.limits());
+        limit(&mut limits);
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    limits,
+                    ..Default::default()
+                },
+                None,
+            )
+            .await
+            .ok()?;
+        let renderer = Renderer::new(&device, FORMAT);
+        let converter = parts::Converter::new(&device);
+        let mipmapper = parts::Mipmapper::new(&device, Downsample::Box);
+        let pool = parts::Pool::new(1, Some(mipmapper));
+        Some(Gpu {
+            device,
+            queue,
+            renderer,
+            converter,
+            pool,
+        })
+    })
+}
+
+// draw `raster` fit to a `width` x `height` target over `background`
+fn render(
+    gpu: &mut Gpu,
+    raster: &Raster,
+    width: u32,
+    height: u32,
+    background: wgpu::Color,
+) -> image::RgbaImage {
+    let job = Job {
+        page: 0,
+        res: 1.0,
+        options: Options::default(),
+    };
+    let layout = &gpu.renderer.texture_bind_group_layout;
+    let slot = parts::PageSlot::new(
+        &gpu.device,
+        &gpu.queue,
+        &gpu.converter,
+        layout,
+        &mut gpu.pool,
+        job,
+        raster,
+    )
+    .unwrap();
+    let window = PhysicalSize::new(width, height);
+    let size = parts::page_pixels(Fit::Page, raster.size, window, 1.0);
+    slot.place(&gpu.queue, parts::place(size, window));
+    gpu.renderer.set_camera(&gpu.queue, &Camera::new());
+    let atlas = parts::Atlas::new(&gpu.device, layout);
+
+    let target = Offscreen::new(&gpu.device, width, height, FORMAT);
+    let mut encoder = gpu
+        .device
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+    gpu.renderer
+        .draw(&mut encoder, target.view(), background, [&slot], &atlas);
+    gpu.queue.submit(std::iter::once(encoder.finish()));
+    target.read(&gpu.device, &gpu.queue).unwrap()
+}
+
+fn root() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+}
+
+// compare with tests/golden/<name>.png, leaving the actual image next to
+// the test binaries when they differ
+fn check_golden(name: &str, actual: &image::RgbaImage) {
+    let golden: PathBuf = root().join("tests/golden").join(format!("{}.png", name));
+    if std::env::var_os("XJ9_BLESS").is_some() {
+        actual.save(&golden).unwrap();
+        eprintln!("wrote {}", golden.display());
+        return;
+    }
+    assert!(
+        golden.exists(),
+        "no golden at {}, run with XJ9_BLESS=1 to write it",
+        golden.display()
+    );
+    let expected = image::open(&golden).unwrap().into_rgba8();
+    assert_eq!(expected.dimensions(), actual.dimensions(), "{} changed size", name);
+    let different = expected
+        .pixels()
+        .zip(actual.pixels())
+        .filter(|(e, a)| e.0.iter().zip(a.0).any(|(e, a)| e.abs_diff(a) > TOLERANCE))
+        .count();
+    let share = different as f64 / (expected.width() * expected.height()) as f64;
+    if share > MAX_DIFFERENT {
+        let out = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.png", name));
+        actual.save(&out).unwrap();
+        panic!(
+            "{} differs from its golden in {:.2}% of pixels, see {}",
+            name,
+            share * 100.0,
+            out.display()
+        );
+    }
+}
+
+fn assert_color(image: &image::RgbaImage, x: u32, y: u32, rgb: [u8; 3]) {
+    let px = image.get_pixel(x, y).0;
+    assert!(
+        px[..3].iter().zip(rgb).all(|(p, c)| p.abs_diff(c) <= TOLERANCE),
+        "pixel {}, {} is {:?}, expected {:?}",
+        x,
+        y,
+        px,
+        rgb
+    );
+}
+
+// a scanned page as a raster, premultiplied like mupdf's
+fn image_raster(path: &Path) -> Raster {
+    let image = image::open(path).unwrap().into_rgba8();
+    let (width, height) = image.dimensions();
+    let mut samples = image.into_raw();
+    for px in samples.chunks_exact_mut(4) {
+        let a = px[3] as u32;
+        for c in &mut px[..3] {
+            *c = (*c as u32 * a / 255) as u8;
+        }
+    }
+    Raster {
+        size: (width as f32, height as f32),
+        width,
+        height,
+        stride: 4 * width,
+        format: parts::Format::RGBA,
+        samples,
+        mask: None,
+    }
+}
+
+#[test]
+fn scanned_page() {
+    let Some(mut gpu) = gpu() else {
+        eprintln!("no software adapter, skipping");
+        return;
+    };
+    let raster = image_raster(&root().join("assets/K&R.png"));
+    let image = render(&mut gpu, &raster, 480, 640, wgpu::Color::BLACK);
+    check_golden("k_and_r", &image);
+}
+
+#[test]
+fn vector_page() {
+    let Some(mut gpu) = gpu() else {
+        eprintln!("no software adapter, skipping");
+        return;
+    };
+    let path = root().join("tests/fixtures/shapes.pdf");
+    let doc = mupdf::document::Document::open(path.to_str().unwrap()).unwrap();
+    let job = Job {
+        page: 0,
+        res: 2.0,
+        options: Options::default(),
+    };
+    let raster = parts::rasterize(&doc, job).unwrap();
+    // the 200 x 100 point page fills the width and sits 50 pixels down
+    let image = render(&mut gpu, &raster, 400, 300, wgpu::Color::BLACK);
+    assert_color(&image, 200, 20, [0, 0, 0]);
+    assert_color(&image, 5, 55, [255, 255, 255]);
+    assert_color(&image, 110, 150, [255, 0, 0]);
+    assert_color(&image, 200, 150, [255, 255, 255]);
+    assert_color(&image, 290, 150, [0, 0, 255]);
+    assert_color(&image, 200, 280, [0, 0, 0]);
+    check_golden("shapes", &image);
+}
+
+#[test]
+fn themed_page() {
+    let Some(mut gpu) = gpu() else {
+        eprintln!("no software adapter, skipping");
+        return;
+    };
+    // bare paper on top, solid black ink below, and an image over the
+    // left quarter
+    let (width, height) = (64, 64);
+    let mut samples = vec![0u8; (width * height * 4) as usize];
+    for px in samples[(width * height * 2) as usize..].chunks_exact_mut(4) {
+        px[3] = 255;
+    }
+    let mut mask = vec![0u8; (width * height) as usize];
+    for row in mask.chunks_exact_mut(width as usize) {
+        row[..16].fill(255);
+    }
+    let raster = Raster {
+        size: (width as f32, height as f32),
+        width,
+        height,
+        stride: 4 * width,
+        format: parts::Format::RGBA,
+        samples,
+        mask: Some(mask),
+    };
+    gpu.renderer.set_theme(&gpu.queue, Theme::Sepia, Vision::Normal);
+    let image = render(&mut gpu, &raster, 64, 64, wgpu::Color::BLACK);
+    assert_color(&image, 32, 8, [0xf4, 0xec, 0xd8]);
+    assert_color(&image, 32, 56, [0x5b, 0x46, 0x36]);
+    assert_color(&image, 8, 8, [255, 255, 255]);
+    assert_color(&image, 8, 56, [0, 0, 0]);
+}
+
+#[test]
+fn vision_filters() {
+    let Some(mut gpu) = gpu() else {
+        eprintln!("no software adapter, skipping");
+        return;
+    };
+    let (width, height) = (16, 16);
+    let samples = [255, 0, 0, 255].repeat((width * height) as usize);
+    let raster = Raster {
+        size: (width as f32, height as f32),
+        width,
+        height,
+        stride: 4 * width,
+        format: parts::Format::RGBA,
+        samples,
+        mask: None,
+    };
+    // pure red as a protanope sees it, and as its luminance
+    gpu.renderer.set_theme(&gpu.queue, Theme::Normal, Vision::Protanopia);
+    let image = render(&mut gpu, &raster, 16, 16, wgpu::Color::BLACK);
+    assert_color(&image, 8, 8, [109, 95, 0]);
+    gpu.renderer.set_theme(&gpu.queue, Theme::Normal, Vision::Grayscale);
+    let image = render(&mut gpu, &raster, 16, 16, wgpu::Color::BLACK);
+    assert_color(&image, 8, 8, [127, 127, 127]);
+}
+
+#[test]
+fn post_passes() {
+    let Some(gpu) = gpu() else {
+        eprintln!("no software adapter, skipping");
+        return;
+    };
+    let pass = |body: &str| {
+        format!(
+            "@group(0) @binding(0) var source: texture_2d<f32>;
+            @group(0) @binding(1) var source_sampler: sampler;
+            @fragment
+            fn fs_main(@location(0) uv: vec2<f32>) -> @location(0) vec4<f32> {{
+                let color = textureSample(source, source_sampler, uv);
+                return {};
+            }}",
+            body
+        )
+    };
+    let sources = [
+        ("swap".to_string(), pass("vec4<f32>(color.bgr, 1.0)")),
+        ("broken".to_string(), pass("color.rgb")),
+        (
+            "invert".to_string(),
+            pass("vec4<f32>(1.0 - color.rgb, 1.0)"),
+        ),
+    ];
+    let mut post = Post::new(&gpu.device, FORMAT);
+    let errors = post.load(&gpu.device, &sources);
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+    assert!(errors[0].starts_with("broken:"), "{}", errors[0]);
+
+    // red pages, blue once swapped and yellow once inverted
+    post.resize(&gpu.device, 16, 16);
+    post.set_uniform(&gpu.queue, [16.0, 16.0], [16.0, 16.0], [0.0, 0.0], 0.0);
+    let atlas = parts::Atlas::new(&gpu.device, &gpu.renderer.texture_bind_group_layout);
+    let target = Offscreen::new(&gpu.device, 16, 16, FORMAT);
+    let mut encoder = gpu
+        .device
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+    let red = wgpu::Color {
+        r: 1.0,
+        g: 0.0,
+        b: 0.0,
+        a: 1.0,
+    };
+    gpu.renderer
+        .draw(&mut encoder, post.input(), red, [], &atlas);
+    post.run(&mut encoder, target.view());
+    gpu.queue.submit(std::iter::once(encoder.finish()));
+    let image = target.read(&gpu.device, &gpu.queue).unwrap();
+    assert_color(&image, 8, 8, [255, 255, 0]);
+}
+
+#[test]
+fn shader_reload() {
+    let Some(mut gpu) = gpu() else {
+        eprintln!("no software adapter, skipping");
+        return;
+    };
+    // bare paper
+    let (width, height) = (16, 16);
+    let raster = Raster {
+        size: (width as f32, height as f32),
+        width,
+        height,
+        stride: 4 * width,
+        format: parts::Format::RGBA,
+        samples: vec![0; (width * height * 4) as usize],
+        mask: None,
+    };
+    let shader = include_str!("../src/shader.wgsl");
+
+    // a shader that doesn't compile leaves the pages drawn as before
+    let error = gpu
+        .renderer
+        .set_shader(
+            &gpu.device,
+            "broken.wgsl",
+            &shader.replace("fn fs_main", "fn fs_"),
+        )
+        .unwrap_err();
+    assert!(format!("{:#}", error).contains("fs_main"), "{:#}", error);
+    let before = render(&mut gpu, &raster, width, height, wgpu::Color::BLACK);
+    assert_color(&before, 8, 8, [255, 255, 255]);
+
+    let green = shader.replace(
+        "return vec4<f32>(vision(color), 1.0);",
+        "return vec4<f32>(0.0, 1.0, 0.0, 1.0);",
+    );
+    gpu.renderer
+        .set_shader(&gpu.device, "green.wgsl", &green)
+        .unwrap();
+    let after = render(&mut gpu, &raster, width, height, wgpu::Color::BLACK);
+    assert_color(&after, 8, 8, [0, 255, 0]);
+}
+
+#[test]
+fn page_bytes() {
+    let Some(mut gpu) = gpu() else {
+        eprintln!("no software adapter, skipping");
+        return;
+    };
+    let (width, height) = (64, 32);
+    let raster = Raster {
+        size: (width as f32, height as f32),
+        width,
+        height,
+        stride: 4 * width,
+        format: parts::Format::RGBA,
+        samples: vec![0; (width * height * 4) as usize],
+        mask: None,
+    };
+    let job = Job {
+        page: 0,
+        res: 1.0,
+        options: Options::default(),
+    };
+    let slot = parts::PageSlot::new(
+        &gpu.device,
+        &gpu.queue,
+        &gpu.converter,
+        &gpu.renderer.texture_bind_group_layout,
+        &mut gpu.pool,
+        job,
+        &raster,
+    )
+    .unwrap();
+    // 64x32 down to 1x1 in rgba, and the 1x1 mask of a page without images
+    let pixels = 64 * 32 + 32 * 16 + 16 * 8 + 8 * 4 + 4 * 2 + 2 + 1;
+    assert_eq!(slot.bytes(), pixels * 4 + 1);
+}
+
+// a mipmapped texture goes back into the pool but is only handed out
+// again for a raster of its own size
+#[test]
+fn mipmapped_reuse() {
+    let Some(mut gpu) = gpu() else {
+        eprintln!("no software adapter, skipping");
+        return;
+    };
+    let first = upload(&mut gpu, 64, 64);
+    assert_eq!(size(&first), (64, 64));
+    gpu.pool.recycle(first);
+    let smaller = upload(&mut gpu, 60, 60);
+    assert_eq!(size(&smaller), (60, 60));
+    gpu.pool.recycle(smaller);
+    assert_eq!(size(&upload(&mut gpu, 60, 60)), (60, 60));
+}
+
+// a white `width` x `height` page through the pool
+fn upload(gpu: &mut Gpu, width: u32, height: u32) -> parts::Texture {
+    let pixels = parts::Pixels {
+        bytes: &vec![255; (width * height * 4) as usize],
+        width,
+        height,
+        stride: 4 * width,
+        format: parts::Format::RGBA,
+    };
+    gpu.pool
+        .upload(&gpu.device, &gpu.queue, &gpu.converter, &pixels, "page")
+        .unwrap()
+}
+
+fn size(texture: &parts::Texture) -> (u32, u32) {
+    (texture.texture.width(), texture.texture.height())
+}
+
+// `pixels` converted into an rgba texture and read back
+fn convert(gpu: &Gpu, pixels: &parts::Pixels) -> Vec<u8> {
+    let (width, height) = (pixels.width, pixels.height);
+    let texture = gpu.device.create_texture(&wgpu::TextureDescriptor {
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba8Unorm,
+        usage: wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::COPY_SRC,
+        label: Some("converted"),
+        view_formats: &[],
+    });
+    gpu.converter
+        .write(&gpu.device, &gpu.queue, &texture, pixels, 0, 0);
+
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    let row = (4 * width).div_ceil(align) * align;
+    let buffer = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("converted readback"),
+        size: (row * height) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut encoder = gpu
+        .device
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(row),
+                rows_per_image: Some(height),
+            },
+        },
+        texture.size(),
+    );
+    gpu.queue.submit(std::iter::once(encoder.finish()));
+    let slice = buffer.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |result| result.unwrap());
+    gpu.device.poll(wgpu::Maintain::Wait);
+    let mapped = slice.get_mapped_range();
+    mapped
+        .chunks(row as usize)
+        .flat_map(|line| &line[..(4 * width) as usize])
+        .copied()
+        .collect()
+}
+
+// rows of `pixel(x, y)` samples, each padded to `stride` bytes with junk
+// that must not show up in the output
+fn padded(width: u32, height: u32, stride: u32, pixel: impl Fn(u32, u32) -> Vec<u8>) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for y in 0..height {
+        let start = bytes.len();
+        for x in 0..width {
+            bytes.extend(pixel(x, y));
+        }
+        bytes.resize(start + stride as usize, 0xa5);
+    }
+    bytes
+}
+
+// gray and cmyk pixmaps with strides no multiple of the copy alignment,
+// through the compute shader and, with storage bindings too small for
+// them, through the cpu
+#[test]
+fn convert_formats() {
+    let (width, height) = (13, 5);
+    let shade = |x: u32, y: u32| (x * 19 + y * 7) as u8;
+    let gray = padded(width, height, width + 3, |x, y| vec![shade(x, y)]);
+    // cyan, magenta, yellow, black and none, so every channel is exact
+    let inks: [([u8; 4], [u8; 3]); 5] = [
+        ([255, 0, 0, 0], [0, 255, 255]),
+        ([0, 255, 0, 0], [255, 0, 255]),
+        ([0, 0, 255, 0], [255, 255, 0]),
+        ([0, 0, 0, 255], [0, 0, 0]),
+        ([0, 0, 0, 0], [255, 255, 255]),
+    ];
+    let cmyk = padded(width, height, 4 * width + 2, |x, _| inks[x as usize % 5].0.to_vec());
+    for limited in [false, true] {
+        // one at a time, gl contexts don't share a thread
+        let gpu = if limited {
+            gpu_with(|limits| limits.max_storage_buffer_binding_size = 64)
+        } else {
+            gpu()
+        };
+        let Some(gpu) = gpu else {
+            eprintln!("no software adapter, skipping");
+            return;
+        };
+        let pixels = parts::Pixels {
+            bytes: &gray,
+            width,
+            height,
+            stride: width + 3,
+            format: parts::Format {
+                colors: parts::Colors::Gray,
+                alpha: false,
+            },
+        };
+        let rgba = convert(&gpu, &pixels);
+        for (i, px) in rgba.chunks_exact(4).enumerate() {
+            let (x, y) = (i as u32 % width, i as u32 / width);
+            let g = shade(x, y);
+            assert_eq!(px, [g, g, g, 255], "gray at {}, {}", x, y);
+        }
+
+        let pixels = parts::Pixels {
+            bytes: &cmyk,
+            width,
+            height,
+            stride: 4 * width + 2,
+            format: parts::Format {
+                colors: parts::Colors::Cmyk,
+                alpha: false,
+            },
+        };
+        let rgba = convert(&gpu, &pixels);
+        for (i, px) in rgba.chunks_exact(4).enumerate() {
+            let (x, y) = (i as u32 % width, i as u32 / width);
+            let [r, g, b] = inks[x as usize % 5].1;
+            assert_eq!(px, [r, g, b, 255], "cmyk at {}, {}", x, y);
+        }
+    }
+}
+
+// a viewer left alone stops drawing once its page is up
+#[test]
+fn idle_viewer() {
+    let Some(mut host) = Host::open("shapes.pdf", 400, 300) else {
+        eprintln!("no software adapter, skipping");
+        return;
+    };
+    host.wait_for(|image| image.get_pixel(110, 150).0[..3] == [255, 0, 0]);
+    let frames = host.viewer.frames();
+    assert!(frames > 0);
+    for _ in 0..50 {
+        host.idle();
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(host.viewer.frames(), frames);
+}
+
+// a viewer on the software adapter, driven the way the event loop
+// drives one
+struct Host {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    target: Offscreen,
+    viewer: Viewer,
+}
+
+impl Host {
+    fn open(fixture: &str, width: u32, height: u32) -> Option<Self> {
+        let gpu = gpu()?;
+        let path = root().join("tests/fixtures").join(fixture);
+        let (device, queue) = (Arc::new(gpu.device), Arc::new(gpu.queue));
+        let viewer = Viewer::new(
+            device.clone(),
+            queue.clone(),
+            FORMAT,
+            path.to_str().unwrap(),
+            PhysicalSize::new(width, height),
+            Settings::default(),
+            || {},
+        )
+        .unwrap();
+        let target = Offscreen::new(&device, width, height, FORMAT);
+        Some(Self {
+            device,
+            queue,
+            target,
+            viewer,
+        })
+    }
+
+    // what the event loop does once its queue is empty
+    fn idle(&mut self) {
+        if self.viewer.due(Instant::now()) {
+            self.viewer.update();
+            let mut encoder = self
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+            self.viewer.draw(&mut encoder, self.target.view());
+            self.queue.submit(std::iter::once(encoder.finish()));
+        }
+    }
+
+    // idle until what is on screen passes `shown`, giving up after a while
+    fn wait_for(&mut self, shown: impl Fn(&image::RgbaImage) -> bool) -> image::RgbaImage {
+        let start = Instant::now();
+        loop {
+            self.idle();
+            let image = self.target.read(&self.device, &self.queue).unwrap();
+            if shown(&image) {
+                return image;
+            }
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "the page never showed"
+            );
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+// a page too big for a texture at the base resolution is drawn smaller
+// instead of failing to upload
+#[test]
+fn oversized_page() {
+    let Some(mut host) = Host::open("a0.pdf", 300, 400) else {
+        eprintln!("no software adapter, skipping");
+        return;
+    };
+    host.wait_for(|image| image.get_pixel(150, 200).0[..3] == [255, 0, 0]);
+}